@@ -0,0 +1,53 @@
+use std::convert::TryInto;
+
+use ssh2::{PublicKey, Session};
+
+use sha2::{Digest, Sha256};
+
+use crate::error::{Context, Error, Kind};
+
+// key loaded in the ssh agent
+pub struct Identity {
+    pub key_type: String,
+    pub fingerprint: String,
+    pub comment: String,
+}
+
+// all keys of the running ssh agent
+pub fn identities() -> Result<Vec<Identity>, Error> {
+    let session = Session::new().context(Kind::Auth, "unable to create session")?;
+    let mut agent = session.agent().context(Kind::Auth, "unable to use ssh agent")?;
+
+    agent.connect().context(Kind::Auth, "ssh agent is not running or SSH_AUTH_SOCK is not set")?;
+    agent.list_identities().context(Kind::Auth, "unable to list ssh agent keys")?;
+
+    Ok(agent.identities().context(Kind::Auth, "unable to list ssh agent keys")?.iter().map(identity).collect())
+}
+
+// fail before any host is contacted if agent authentication can not work
+pub fn preflight() -> Result<Vec<Identity>, Error> {
+    let identities = identities()?;
+
+    match identities.is_empty() {
+        true => Err(Error::new(Kind::Auth, "ssh agent has no keys loaded, add one with ssh-add")),
+        false => Ok(identities),
+    }
+}
+
+fn identity(key: &PublicKey) -> Identity {
+    let blob = key.blob();
+
+    // the blob starts with the length prefixed key type
+    let key_type = blob.get(..4)
+        .and_then(|length| length.try_into().ok())
+        .map(|length: [u8; 4]| u32::from_be_bytes(length) as usize)
+        .and_then(|length| blob.get(4..4 + length))
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+
+    Identity {
+        key_type,
+        fingerprint: format!("SHA256:{}", base64::encode_config(Sha256::digest(blob), base64::STANDARD_NO_PAD)),
+        comment: key.comment().to_string(),
+    }
+}
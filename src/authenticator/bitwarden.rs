@@ -8,12 +8,14 @@ use std::str::from_utf8;
 use serde::Deserialize;
 use serde_json::Value;
 
-use super::Authenticator;
+use super::{unsupported, Authenticator, Reference};
 
 #[derive(Debug, Deserialize)]
 struct Item {
     name: Option<String>,
     login: Option<LoginItem>,
+    // custom fields of the item
+    fields: Option<Vec<CustomField>>,
 
     #[serde(flatten)]
     extra: HashMap<String, Value>
@@ -23,18 +25,25 @@ struct Item {
 struct LoginItem {
     username: Option<String>,
     password: Option<String>,
+    totp: Option<String>,
 
     #[serde(flatten)]
     extra: HashMap<String, Value>
 }
 
+#[derive(Debug, Deserialize)]
+struct CustomField {
+    name: Option<String>,
+    value: Option<String>,
+}
+
 pub struct Bitwarden {
     secrets: Vec<Item>
 }
 
-impl Authenticator for Bitwarden {
+impl Bitwarden {
     // read bitwarden output, bitwarden cli must logged in before
-    fn new(master_password: &str) -> Result<Self, Box<dyn Error>> {
+    pub fn new(master_password: &str) -> Result<Self, Box<dyn Error>> {
         let output = Command::new("bw")
             .arg("unlock")
             .arg(master_password)
@@ -61,38 +70,57 @@ impl Authenticator for Bitwarden {
         })
     }
 
-    // get first matching password
-    fn get(&self, name: &str, username: &str) -> Result<&str, Box<dyn Error>> {
-        // TODO: fix checks with ? synatx if possible
-        for item in &self.secrets {
-            match &item.name {
-                Some(item_name) => {
-                    // check if hostname appears in bitwarden name
-                    if item_name.to_lowercase().contains(&name.to_lowercase()) {
-                        match &item.login {
-                            Some(item_login) => {
-                                match &item_login.username {
-                                    Some(item_username) => {
-                                        // check if username appears in bitwarden username
-                                        if item_username.contains(&username) {
-                                            match &item_login.password {
-                                                // password found
-                                                Some(item_password) => return Ok(item_password),
-                                                None => ()
-                                            }
-                                        }
-                                    },
-                                    None => ()
-                                }
-                            },
-                            None => ()
-                        }
-                    }
-                },
-                None => ()
+    // a named item, otherwise the first login with the host name in its name
+    // and the user in its username
+    fn find(&self, item: Option<&str>, name: &str, user: &str) -> Option<&Item> {
+        self.secrets.iter().find(|i| {
+            let item_name = i.name.as_deref().unwrap_or_default().to_lowercase();
+            match item {
+                Some(item) => item_name == item.to_lowercase(),
+                None => item_name.contains(&name.to_lowercase())
+                    && i.login.as_ref().and_then(|l| l.username.as_deref()).is_some_and(|u| u.contains(user)),
             }
+        })
+    }
+}
+
+impl Item {
+    fn field(&self, field: &str) -> Option<&str> {
+        let login = self.login.as_ref();
+        match field {
+            "password" => login.and_then(|l| l.password.as_deref()),
+            "username" => login.and_then(|l| l.username.as_deref()),
+            "totp" => login.and_then(|l| l.totp.as_deref()),
+            _ => self.fields.iter().flatten().find(|f| f.name.as_deref() == Some(field)).and_then(|f| f.value.as_deref()),
         }
+    }
+}
 
-        Err(Box::new(io::Error::new(io::ErrorKind::NotFound, "password in bitwarden not found!")))
+impl Authenticator for Bitwarden {
+    // named items must exist in the vault
+    fn check(&self, reference: &Reference) -> Result<(), String> {
+        match reference {
+            Reference::Bitwarden { item: Some(item), .. } => match self.find(Some(item), "", "") {
+                Some(_) => Ok(()),
+                None => Err(format!("item {} not found in bitwarden", item)),
+            },
+            Reference::Bitwarden { item: None, .. } => Ok(()),
+            _ => Err(unsupported(reference)),
+        }
     }
-}
\ No newline at end of file
+
+    fn get(&self, reference: &Reference, name: &str, user: &str) -> Result<String, Box<dyn Error>> {
+        let (item, field) = match reference {
+            Reference::Bitwarden { item, field } => (item.as_deref(), field.as_deref().unwrap_or("password")),
+            _ => return Err(unsupported(reference).into()),
+        };
+
+        match self.find(item, name, user) {
+            Some(found) => match found.field(field) {
+                Some(secret) => Ok(secret.to_string()),
+                None => Err(Box::new(io::Error::new(io::ErrorKind::NotFound, format!("field {} not found in bitwarden item", field)))),
+            },
+            None => Err(Box::new(io::Error::new(io::ErrorKind::NotFound, "password in bitwarden not found!"))),
+        }
+    }
+}
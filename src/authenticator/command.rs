@@ -0,0 +1,53 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::process::{Command, Stdio};
+use std::str::from_utf8;
+use std::sync::Mutex;
+
+use super::{unsupported, Authenticator, Reference};
+
+// secrets printed by local commands (e.g. pass show deploy), every command
+// runs only once and its output is used for all hosts
+#[derive(Default)]
+pub struct Program {
+    outputs: Mutex<HashMap<String, String>>,
+}
+
+impl Authenticator for Program {
+    fn check(&self, reference: &Reference) -> Result<(), String> {
+        match reference {
+            Reference::Command { cmd } if cmd.trim().is_empty() => Err("empty command".to_string()),
+            Reference::Command { .. } => Ok(()),
+            _ => Err(unsupported(reference)),
+        }
+    }
+
+    fn get(&self, reference: &Reference, _name: &str, _user: &str) -> Result<String, Box<dyn Error>> {
+        let cmd = match reference {
+            Reference::Command { cmd } => cmd,
+            _ => return Err(unsupported(reference).into()),
+        };
+
+        // held while the command runs, so a prompting command asks only once
+        let mut outputs = self.outputs.lock().map_err(|_| "command outputs poisoned")?;
+        if let Some(output) = outputs.get(cmd) {
+            return Ok(output.clone())
+        }
+
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(cmd)
+            .stdin(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .output()?;
+
+        if !output.status.success() {
+            return Err(format!("command '{}' failed with {}", cmd, output.status).into());
+        }
+
+        let secret = from_utf8(&output.stdout)?.trim_end_matches(['\n', '\r']).to_string();
+        outputs.insert(cmd.to_string(), secret.clone());
+
+        Ok(secret)
+    }
+}
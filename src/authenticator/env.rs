@@ -0,0 +1,28 @@
+use std::env;
+use std::error::Error;
+use std::io;
+
+use super::{unsupported, Authenticator, Reference};
+
+// secrets from environment variables of tui-patch
+pub struct Environment;
+
+impl Authenticator for Environment {
+    fn check(&self, reference: &Reference) -> Result<(), String> {
+        match reference {
+            Reference::Env { name } if name.is_empty() || name.contains('=') => Err(format!("invalid environment variable name '{}'", name)),
+            Reference::Env { name } => match env::var_os(name) {
+                Some(_) => Ok(()),
+                None => Err(format!("environment variable {} is not set", name)),
+            },
+            _ => Err(unsupported(reference)),
+        }
+    }
+
+    fn get(&self, reference: &Reference, _name: &str, _user: &str) -> Result<String, Box<dyn Error>> {
+        match reference {
+            Reference::Env { name } => env::var(name).map_err(|e| Box::new(io::Error::new(io::ErrorKind::NotFound, format!("environment variable {}: {}", name, e))) as Box<dyn Error>),
+            _ => Err(unsupported(reference).into()),
+        }
+    }
+}
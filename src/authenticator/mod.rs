@@ -1,8 +1,115 @@
 pub mod bitwarden;
+pub mod command;
+pub mod env;
+pub mod vault;
 
 use std::error::Error;
+use std::fmt;
 
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use bitwarden::Bitwarden;
+use command::Program;
+use env::Environment;
+use vault::Vault;
+
+// a secret store, references are checked before anything is connected
 pub trait Authenticator {
-    fn new(master_password: &str) -> Result<Self, Box<dyn Error>> where Self: Sized;
-    fn get(&self, hostname: &str, user: &str) -> Result<&str, Box<dyn Error>>;
-}
\ No newline at end of file
+    fn check(&self, reference: &Reference) -> Result<(), String>;
+    // name is the host or the key file name, used if the reference names no item
+    fn get(&self, reference: &Reference, name: &str, user: &str) -> Result<String, Box<dyn Error>>;
+}
+
+// a password, passphrase or totp secret, either written in the file or
+// read from a secret store
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Secret {
+    Literal(String),
+    Reference(Reference),
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(tag = "from", rename_all = "lowercase")]
+pub enum Reference {
+    // field (default password) of a bitwarden item, without an item the first
+    // login with the host name in its name and the user in its username
+    Bitwarden {
+        item: Option<String>,
+        field: Option<String>,
+    },
+    // environment variable of tui-patch
+    Env {
+        name: String,
+    },
+    // output of a local command without the trailing line break
+    Command {
+        cmd: String,
+    },
+    // entry of the encrypted vault file given with --vault
+    Vault {
+        name: String,
+    },
+}
+
+// all secret stores, a reference is passed to the store it is from
+pub struct Stores {
+    pub bitwarden: Option<Bitwarden>,
+    pub vault: Option<Vault>,
+    pub environment: Environment,
+    pub program: Program,
+}
+
+impl Stores {
+    pub fn new(bitwarden: Option<Bitwarden>, vault: Option<Vault>) -> Self {
+        Stores {
+            bitwarden,
+            vault,
+            environment: Environment,
+            program: Program::default(),
+        }
+    }
+
+    fn store(&self, reference: &Reference) -> Result<&dyn Authenticator, String> {
+        match reference {
+            Reference::Bitwarden { .. } => match &self.bitwarden {
+                Some(bitwarden) => Ok(bitwarden),
+                None => Err("bitwarden is not unlocked, use --bitwarden".to_string()),
+            },
+            Reference::Vault { .. } => match &self.vault {
+                Some(vault) => Ok(vault),
+                None => Err("no vault is open, use --vault".to_string()),
+            },
+            Reference::Env { .. } => Ok(&self.environment),
+            Reference::Command { .. } => Ok(&self.program),
+        }
+    }
+}
+
+impl Authenticator for Stores {
+    fn check(&self, reference: &Reference) -> Result<(), String> {
+        self.store(reference)?.check(reference)
+    }
+
+    fn get(&self, reference: &Reference, name: &str, user: &str) -> Result<String, Box<dyn Error>> {
+        self.store(reference)?.get(reference, name, user)
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Reference::Bitwarden { item: Some(item), .. } => write!(f, "bitwarden item {}", item),
+            Reference::Bitwarden { item: None, .. } => write!(f, "bitwarden"),
+            Reference::Env { name } => write!(f, "environment variable {}", name),
+            Reference::Command { cmd } => write!(f, "command '{}'", cmd),
+            Reference::Vault { name } => write!(f, "vault entry {}", name),
+        }
+    }
+}
+
+// error for a reference passed to the wrong store
+fn unsupported(reference: &Reference) -> String {
+    format!("{} is not supported by this store", reference)
+}
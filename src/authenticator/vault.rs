@@ -0,0 +1,192 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error as StdError;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::process::{self, Command};
+
+use argon2::Argon2;
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use chacha20poly1305::aead::{Aead, AeadCore, OsRng};
+use chacha20poly1305::aead::rand_core::RngCore;
+
+use crate::error::{Context, Error, Kind};
+
+use super::{unsupported, Authenticator, Reference};
+
+// first line of a vault file, the rest is base64 of salt, nonce and ciphertext
+const HEADER: &str = "$TUI-PATCH-VAULT;1;ARGON2ID;CHACHA20-POLY1305";
+const SALT_LENGTH: usize = 16;
+const NONCE_LENGTH: usize = 12;
+
+// encrypted file with secrets as yaml map of name: secret, the key is derived
+// from a passphrase or the content of a key file
+pub struct Vault {
+    entries: BTreeMap<String, String>,
+}
+
+impl Vault {
+    pub fn new(path: &Path, key: &[u8]) -> Result<Self, Error> {
+        Ok(Vault { entries: entries(&decrypt(path, key)?)? })
+    }
+}
+
+impl Authenticator for Vault {
+    fn check(&self, reference: &Reference) -> Result<(), String> {
+        match reference {
+            Reference::Vault { name } if self.entries.contains_key(name) => Ok(()),
+            Reference::Vault { name } => Err(format!("entry {} not found in vault", name)),
+            _ => Err(unsupported(reference)),
+        }
+    }
+
+    fn get(&self, reference: &Reference, _name: &str, _user: &str) -> Result<String, Box<dyn StdError>> {
+        match reference {
+            Reference::Vault { name } => self.entries.get(name).cloned().ok_or_else(|| format!("entry {} not found in vault", name).into()),
+            _ => Err(unsupported(reference).into()),
+        }
+    }
+}
+
+// the yaml text, comments are kept for editing
+pub fn decrypt(path: &Path, key: &[u8]) -> Result<String, Error> {
+    let content = fs::read_to_string(path).context(Kind::Config, &format!("unable to read vault {}", path.display()))?;
+
+    let mut lines = content.lines();
+    if lines.next() != Some(HEADER) {
+        return Err(Error::new(Kind::Config, format!("{} is not a vault file", path.display())))
+    }
+    let data = base64::decode(lines.collect::<String>()).context(Kind::Config, &format!("vault {} is damaged", path.display()))?;
+    if data.len() < SALT_LENGTH + NONCE_LENGTH {
+        return Err(Error::new(Kind::Config, format!("vault {} is damaged", path.display())))
+    }
+
+    let (salt, rest) = data.split_at(SALT_LENGTH);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LENGTH);
+    let plaintext = cipher(key, salt)?.decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::new(Kind::Auth, format!("unable to decrypt vault {}, wrong passphrase or key file", path.display())))?;
+
+    String::from_utf8(plaintext).context(Kind::Config, &format!("vault {} is damaged", path.display()))
+}
+
+// every write gets a new salt and nonce
+pub fn encrypt(path: &Path, key: &[u8], text: &str) -> Result<(), Error> {
+    entries(text)?;
+
+    let mut salt = [0u8; SALT_LENGTH];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher(key, &salt)?.encrypt(&nonce, text.as_bytes())
+        .map_err(|_| Error::new(Kind::Config, format!("unable to encrypt vault {}", path.display())))?;
+
+    let data = base64::encode([&salt[..], &nonce[..], &ciphertext[..]].concat());
+    let mut content = HEADER.to_string();
+    for line in data.as_bytes().chunks(64) {
+        content.push('\n');
+        content.push_str(&String::from_utf8_lossy(line));
+    }
+    content.push('\n');
+
+    // written next to the vault and renamed over it, so a failed write never
+    // leaves a damaged vault behind
+    let message = format!("unable to write vault {}", path.display());
+    let name = path.file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_default();
+    let temporary = path.with_file_name(format!(".{}.{}.tmp", name, process::id()));
+    let written = fs::write(&temporary, content)
+        .and_then(|_| match fs::metadata(path) {
+            Ok(metadata) => fs::set_permissions(&temporary, metadata.permissions()),
+            Err(_) => Ok(()),
+        })
+        .and_then(|_| fs::rename(&temporary, path));
+    if written.is_err() {
+        let _ = fs::remove_file(&temporary);
+    }
+    written.context(Kind::Config, &message)
+}
+
+fn cipher(key: &[u8], salt: &[u8]) -> Result<ChaCha20Poly1305, Error> {
+    let mut derived = [0u8; 32];
+    Argon2::default().hash_password_into(key, salt, &mut derived)
+        .map_err(|e| Error::new(Kind::Config, format!("unable to derive vault key: {}", e)))?;
+    Ok(ChaCha20Poly1305::new(Key::from_slice(&derived)))
+}
+
+// an empty file or only comments is an empty vault
+fn entries(text: &str) -> Result<BTreeMap<String, String>, Error> {
+    if text.lines().all(|line| line.trim().is_empty() || line.trim_start().starts_with('#')) {
+        return Ok(BTreeMap::new())
+    }
+    serde_yaml::from_str(text).map_err(|e| Error::new(Kind::Config, format!("vault entries must be a map of name: secret: {}", e)))
+}
+
+// content of a key file or a passphrase asked on the terminal, new
+// passphrases are asked twice
+pub fn key(key_file: Option<&Path>, prompt: &str, confirm: bool) -> Result<Vec<u8>, Error> {
+    if let Some(path) = key_file {
+        let key = fs::read(path).context(Kind::Config, &format!("unable to read key file {}", path.display()))?;
+        let length = key.iter().rposition(|b| *b != b'\n' && *b != b'\r').map_or(0, |i| i + 1);
+        return match length {
+            0 => Err(Error::new(Kind::Config, format!("key file {} is empty", path.display()))),
+            _ => Ok(key[..length].to_vec()),
+        }
+    }
+
+    let passphrase = rpassword::read_password_from_tty(Some(prompt)).context(Kind::Config, "unable to read passphrase")?;
+    if confirm && rpassword::read_password_from_tty(Some("Repeat passphrase: ")).context(Kind::Config, "unable to read passphrase")? != passphrase {
+        return Err(Error::new(Kind::Config, "passphrases do not match"))
+    }
+    match passphrase.is_empty() {
+        true => Err(Error::new(Kind::Config, "empty passphrase")),
+        false => Ok(passphrase.into_bytes()),
+    }
+}
+
+// open the text in $VISUAL or $EDITOR (default vi) in a temporary file only
+// readable by the user, the file is removed afterwards; invalid entries open
+// the editor again, so the changes are not lost by a typo
+pub fn edit(text: &str) -> Result<String, Error> {
+    let path = env::temp_dir().join(format!("tui-patch-vault-{}.yaml", process::id()));
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+    let mut file = options.open(&path)
+        .context(Kind::Config, &format!("unable to create {}", path.display()))?;
+    let written = file.write_all(text.as_bytes()).context(Kind::Config, &format!("unable to write {}", path.display()));
+    drop(file);
+
+    let editor = env::var("VISUAL").or_else(|_| env::var("EDITOR")).unwrap_or_else(|_| "vi".to_string());
+    let result = written.and_then(|_| loop {
+        // through the shell, so editors with arguments like 'code --wait' work
+        let status = Command::new("sh").arg("-c").arg(format!("{} \"$1\"", editor)).arg("sh").arg(&path).status()
+            .context(Kind::Config, &format!("unable to start editor {}", editor))?;
+        if !status.success() {
+            break Err(Error::new(Kind::Config, format!("editor {} failed with {}, vault not changed", editor, status)))
+        }
+
+        let text = fs::read_to_string(&path).context(Kind::Config, &format!("unable to read {}", path.display()))?;
+        match entries(&text) {
+            Ok(_) => break Ok(text),
+            Err(e) => {
+                eprintln!("{}", e);
+                if !again()? {
+                    break Err(Error::new(Kind::Config, "vault not changed"))
+                }
+            },
+        }
+    });
+
+    let _ = fs::remove_file(&path);
+    result
+}
+
+// anything but no (or the end of the input) edits again
+fn again() -> Result<bool, Error> {
+    eprint!("Edit again? [Y/n] ");
+    let mut answer = String::new();
+    let read = io::stdin().read_line(&mut answer).context(Kind::Config, "unable to read answer")?;
+    Ok(read > 0 && !answer.trim().eq_ignore_ascii_case("n") && !answer.trim().eq_ignore_ascii_case("no"))
+}
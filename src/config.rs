@@ -1,152 +1,1410 @@
-use std::io::prelude::*;
 use std::net::{TcpStream};
-use std::error::Error;
+use std::convert::TryFrom;
+use std::fmt;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use ssh2::{Session, ErrorCode, ExtendedData};
 
 use serde::Deserialize;
-use std::collections::HashMap;
+use schemars::JsonSchema;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{RootSchema, Schema};
+use std::collections::{BTreeMap, HashMap};
 
 use log::{error, warn, info};
 
-use crate::authenticator::Authenticator;
+use crate::authenticator::{Authenticator, Secret};
+
+use crate::logger;
 
 use crate::resolver::Resolver;
 
-#[derive(Debug, Deserialize)]
+use crate::shell::Shell;
+
+use crate::tunnel::Tunnels;
+
+use crate::interactive::Prompter;
+
+use crate::hostkey::{HostKeyChecking, HostKeys};
+
+use crate::sshconfig::SshConfig;
+
+use crate::net;
+
+use crate::sshoptions::{self, SshOptions};
+
+use crate::error::{Context, Error, Kind};
+
+use crate::format::{self, Format};
+
+use crate::validate;
+
+use crate::inventory::{self, Groups, Members, Pattern};
+
+#[derive(Debug, Deserialize, JsonSchema)]
 pub struct Config {
+    // hosts can also come from an inventory file
+    #[serde(default)]
     pub targets: Vec<Target>,
 
+    // host pattern selecting the targets of a playbook (default all)
+    hosts: Option<String>,
+
+    // variables used as {{ name }} in commands
+    #[serde(default)]
+    vars: HashMap<String, String>,
+
+    // named host groups with shared settings and variables
+    #[serde(default)]
+    groups: BTreeMap<String, Group>,
+
+    // default jump hosts for all targets
+    jump: Option<Jump>,
+
+    // default timeout in seconds for each connection attempt
+    connect_timeout: Option<u64>,
+
+    // default order of authentication methods
+    auth: Option<Vec<AuthMethod>>,
+
+    // default algorithms, compression and keepalive
+    ssh_options: Option<SshOptions>,
+
+    // host key verification against known_hosts
+    host_key_checking: Option<HostKeyChecking>,
+    known_hosts: Option<PathBuf>,
+
+    // configs merged into this one, relative to this file
+    #[serde(default)]
+    import: Vec<PathBuf>,
+
+    // fields of all targets and tasks which do not set them
+    #[serde(default)]
+    defaults: Defaults,
+
+    // steps run one after another, each with its own hosts and tasks
+    #[serde(default)]
+    plays: Vec<Play>,
+
     #[serde(flatten)]
-    tasks: HashMap<String, Vec<Task>>,
+    tasks: HashMap<String, TaskLists>,
 }
 
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
 pub struct Target {
     pub host: String,
     ip: Option<String>,
     port: Option<u16>,
-    user: String,
-    password: Option<String>,
+    user: Option<String>,
+    password: Option<Secret>,
+    identity_file: Option<PathBuf>,
+    identity_passphrase: Option<Secret>,
+    totp_secret: Option<Secret>,
+    auth: Option<Vec<AuthMethod>>,
+    ssh_options: Option<SshOptions>,
+    jump: Option<Jump>,
+    connect_timeout: Option<u64>,
+
+    // HostName from the ssh config
+    #[serde(skip)]
+    hostname: Option<String>,
+    pub shell: Option<ShellMode>,
+
+    // variables of this target, override the ones of the groups and the config
+    #[serde(default)]
+    vars: HashMap<String, String>,
+
+    // groups containing this target from the least to the most specific
+    #[serde(skip)]
+    groups: Vec<String>,
+
+    // inline tasks or names of task lists (default the list named tasks),
+    // resolved into tasks
+    #[serde(rename = "tasks")]
+    task_lists: Option<TaskLists>,
+    #[serde(skip)]
     pub tasks: Vec<Task>,
 }
 
-#[derive(Debug, Deserialize)]
+// settings and variables for all hosts of a group, the ones of a child group
+// win over its parents and the ones of the target win over all groups
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Group {
+    #[serde(flatten)]
+    members: Members,
+    port: Option<u16>,
+    user: Option<String>,
+    password: Option<Secret>,
+    identity_file: Option<PathBuf>,
+    identity_passphrase: Option<Secret>,
+    totp_secret: Option<Secret>,
+    auth: Option<Vec<AuthMethod>>,
+    ssh_options: Option<SshOptions>,
+    jump: Option<Jump>,
+    connect_timeout: Option<u64>,
+    shell: Option<ShellMode>,
+    #[serde(default)]
+    vars: HashMap<String, String>,
+}
+
+// a task list name, or a list of tasks and task list names which are concatenated
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum TaskLists {
+    Name(String),
+    List(Vec<TaskItem>),
+}
+
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum TaskItem {
+    Name(String),
+    // file with a list of tasks and task list names, relative to this file
+    Include { include: PathBuf },
+    Task(TaskSpec),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ShellMode {
+    // every task gets its own channel (default)
+    Session,
+    // all tasks run in one remote shell, state like cd or export carries over
+    Persistent,
+}
+
+// one jump host or a chain of jump hosts, the first one is connected first
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Jump {
+    Chain(Vec<JumpHost>),
+    Hop(Box<JumpHost>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(try_from = "JumpSpec")]
+pub struct JumpHost {
+    host: String,
+    hostname: Option<String>,
+    port: Option<u16>,
+    user: Option<String>,
+    password: Option<Secret>,
+    identity_file: Option<PathBuf>,
+    identity_passphrase: Option<Secret>,
+    totp_secret: Option<Secret>,
+    auth: Option<Vec<AuthMethod>>,
+    ssh_options: Option<SshOptions>,
+}
+
+// jump host either written as [user@]host[:port] or with all fields
+#[derive(Deserialize, JsonSchema)]
+#[serde(untagged)]
+enum JumpSpec {
+    Short(String),
+    Full(Box<JumpFields>),
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct JumpFields {
+    host: String,
+    port: Option<u16>,
+    user: Option<String>,
+    password: Option<Secret>,
+    identity_file: Option<PathBuf>,
+    identity_passphrase: Option<Secret>,
+    totp_secret: Option<Secret>,
+    auth: Option<Vec<AuthMethod>>,
+    ssh_options: Option<SshOptions>,
+}
+
+// authentication methods, tried in the given order
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    Agent,
+    Key,
+    Password,
+    KeyboardInteractive,
+}
+
+// everything needed to authenticate a session
+struct Credentials<'a> {
+    host: &'a str,
+    // name of the host key in known_hosts, the host name of the ssh config
+    // like openssh, not the alias
+    known_host: &'a str,
+    port: u16,
+    user: &'a str,
+    password: &'a Option<Secret>,
+    identity_file: &'a Option<PathBuf>,
+    identity_passphrase: &'a Option<Secret>,
+    totp_secret: &'a Option<Secret>,
+    auth: &'a Option<Vec<AuthMethod>>,
+    ssh_options: &'a Option<SshOptions>,
+}
+
+// hosts (default the top-level hosts pattern), task lists (default the ones
+// of the targets) and strategy of one step of a playbook
+#[derive(Debug, Deserialize, JsonSchema)]
+pub struct Play {
+    name: Option<String>,
+    hosts: Option<String>,
+    tasks: Option<TaskLists>,
+    #[serde(default)]
+    strategy: Strategy,
+    // hosts at the same time with the serial strategy (default 1)
+    batch: Option<usize>,
+    // a failed host stops the following plays (default true)
+    stop_on_error: Option<bool>,
+
+    #[serde(skip)]
+    pub targets: Vec<Target>,
+}
+
+#[derive(Debug, Default, Clone, Copy, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    // all hosts at once (default)
+    #[default]
+    Parallel,
+    // batches of hosts one after another, a failed host stops the next batches
+    Serial,
+}
+
+// values set on a target, its groups, the top-level settings or a task win
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Defaults {
+    user: Option<String>,
+    port: Option<u16>,
+    auth: Option<Vec<AuthMethod>>,
+    connect_timeout: Option<u64>,
+    shell: Option<ShellMode>,
+    // task lists of targets without tasks (default the list named tasks)
+    tasks: Option<TaskLists>,
+    expected_result: Option<i32>,
+    stop_on_error: Option<bool>,
+    r#become: Option<bool>,
+}
+
+// a task as written, fields without a value are taken from the defaults
+#[derive(Debug, Clone, Deserialize, JsonSchema)]
+#[schemars(rename = "Task")]
+struct TaskSpec {
+    command: String,
+    expected_result: Option<i32>,
+    stop_on_error: Option<bool>,
+    // run the command with sudo (needs sudo without password)
+    r#become: Option<bool>,
+}
+
+#[derive(Debug, Clone)]
 pub struct Task {
     command: String,
     expected_result: i32,
     stop_on_error: bool,
+    r#become: bool,
 }
 
+// a failed command with stop_on_error is returned as assertion error
 #[derive(Debug)]
 pub enum State {
-    Failed,
     Warning,
     Ok,
 }
 
+impl Config {
+    // json schema of the config file, every other key is a named task list
+    pub fn schema() -> RootSchema {
+        let mut gen = SchemaGenerator::default();
+        let tasks = gen.subschema_for::<TaskLists>();
+        let mut schema = gen.into_root_schema_for::<Config>();
+        schema.schema.object().additional_properties = Some(Box::new(tasks));
+        schema
+    }
+
+    // add hosts, settings, variables and task lists of an inventory or an
+    // imported config, everything set in this config wins
+    pub fn merge(&mut self, other: Config) {
+        let mut targets = other.targets;
+        targets.append(&mut self.targets);
+        self.targets = targets;
+
+        self.jump = self.jump.take().or(other.jump);
+        self.connect_timeout = self.connect_timeout.or(other.connect_timeout);
+        self.auth = self.auth.take().or(other.auth);
+        self.ssh_options = self.ssh_options.take().or(other.ssh_options);
+        self.host_key_checking = self.host_key_checking.or(other.host_key_checking);
+        self.known_hosts = self.known_hosts.take().or(other.known_hosts);
+        self.defaults.merge(other.defaults);
+        if self.plays.is_empty() {
+            self.plays = other.plays;
+        }
+
+        for (name, value) in other.vars {
+            self.vars.entry(name).or_insert(value);
+        }
+        for (name, tasks) in other.tasks {
+            self.tasks.entry(name).or_insert(tasks);
+        }
+        for (name, group) in other.groups {
+            self.groups.entry(name).or_insert(group);
+        }
+    }
+
+    // read the imported configs and the included task files, later imports
+    // win over earlier ones and the importing file over all of them
+    pub fn resolve_files(&mut self, path: &Path) -> Result<(), Error> {
+        self.resolve_files_from(path, &mut vec![file_id(path)])
+    }
+
+    fn resolve_files_from(&mut self, path: &Path, stack: &mut Vec<PathBuf>) -> Result<(), Error> {
+        let task_lists = self.tasks.values_mut()
+            .chain(self.targets.iter_mut().filter_map(|t| t.task_lists.as_mut()))
+            .chain(self.defaults.tasks.as_mut())
+            .chain(self.plays.iter_mut().filter_map(|p| p.tasks.as_mut()));
+        for task_lists in task_lists {
+            if let TaskLists::List(items) = task_lists {
+                include(items, path, stack)?;
+            }
+        }
+
+        for import in std::mem::take(&mut self.import).iter().rev() {
+            let file = relative(path, import);
+            enter(stack, &file)?;
+            let source = format::read(&file)?;
+            let format = Format::detect(&file);
+            validate::config(&file, &source, format, false)?;
+            let mut imported: Config = format.parse_expanded(&file, &source)?;
+            imported.resolve_files_from(&file, stack)?;
+            stack.pop();
+
+            self.hosts = self.hosts.take().or(imported.hosts.take());
+            self.merge(imported);
+        }
+
+        Ok(())
+    }
+
+    // one target for every host of a range like web[01:40], group hosts as well
+    pub fn expand_ranges(&mut self) -> Result<(), Error> {
+        let mut targets = Vec::new();
+        for target in self.targets.drain(..) {
+            for host in inventory::expand(&target.host).map_err(|e| Error::new(Kind::Config, e))? {
+                targets.push(Target { host, ..target.clone() });
+            }
+        }
+        self.targets = targets;
+
+        for (name, group) in self.groups.iter_mut() {
+            group.members.expand().map_err(|e| Error::new(Kind::Config, format!("group {}: {}", name, e)))?;
+        }
+
+        Ok(())
+    }
+
+    // copy the settings and variables of the groups to their targets, the
+    // most specific group is applied first as set values are kept
+    pub fn apply_groups(&mut self) -> Result<(), Error> {
+        let hosts: Vec<&str> = self.targets.iter().map(|t| t.host.as_str()).collect();
+        let groups = Groups::new(self.groups.iter().map(|(name, group)| (name.as_str(), &group.members)).collect(), &hosts)?;
+
+        let memberships: Vec<Vec<String>> = self.targets.iter()
+            .map(|target| groups.of(&target.host).into_iter().map(String::from).collect())
+            .collect();
+
+        let all = &self.groups;
+        for (target, names) in self.targets.iter_mut().zip(memberships) {
+            for group in names.iter().rev().map(|name| &all[name]) {
+                target.port = target.port.or(group.port);
+                target.user = target.user.take().or_else(|| group.user.clone());
+                target.password = target.password.take().or_else(|| group.password.clone());
+                target.identity_file = target.identity_file.take().or_else(|| group.identity_file.clone());
+                target.identity_passphrase = target.identity_passphrase.take().or_else(|| group.identity_passphrase.clone());
+                target.totp_secret = target.totp_secret.take().or_else(|| group.totp_secret.clone());
+                target.auth = target.auth.take().or_else(|| group.auth.clone());
+                target.jump = target.jump.take().or_else(|| group.jump.clone());
+                target.connect_timeout = target.connect_timeout.or(group.connect_timeout);
+                target.shell = target.shell.or(group.shell);
+
+                if let Some(defaults) = &group.ssh_options {
+                    target.ssh_options = Some(target.ssh_options.as_ref().map_or(defaults.clone(), |o| o.or(defaults)));
+                }
+                for (name, value) in &group.vars {
+                    target.vars.entry(name.clone()).or_insert_with(|| value.clone());
+                }
+            }
+            target.groups = names;
+        }
+
+        Ok(())
+    }
+
+    // keep the targets matching the host pattern, given on the command line
+    // or in the playbook, and the limit
+    pub fn select(&mut self, hosts: Option<&str>, limit: Option<&str>) -> Result<(), Error> {
+        let patterns = [hosts.or(self.hosts.as_deref()), limit].iter().flatten()
+            .map(|pattern| Pattern::parse(pattern))
+            .collect::<Result<Vec<Pattern>, Error>>()?;
+
+        for pattern in patterns {
+            self.targets.retain(|target| pattern.matches(&target.host, &target.groups));
+            if self.targets.is_empty() {
+                return Err(Error::new(Kind::Config, format!("no hosts match '{}'", pattern)))
+            }
+        }
+
+        Ok(())
+    }
+
+    // copy config wide defaults to all targets
+    pub fn apply_defaults(&mut self) {
+        let defaults = &self.defaults;
+        for target in self.targets.iter_mut() {
+            if target.jump.is_none() {
+                target.jump = self.jump.clone();
+            }
+            target.connect_timeout = target.connect_timeout.or(self.connect_timeout).or(defaults.connect_timeout);
+            if target.auth.is_none() {
+                target.auth = self.auth.clone().or_else(|| defaults.auth.clone());
+            }
+            target.shell = target.shell.or(defaults.shell);
+
+            // single ssh options are merged with the defaults
+            if let Some(defaults) = &self.ssh_options {
+                target.ssh_options = Some(target.ssh_options.as_ref().map_or(defaults.clone(), |o| o.or(defaults)));
+            }
+        }
+    }
+
+    // the plays with their targets and tasks, a file without plays is one
+    // play with the targets selected by --hosts or the hosts pattern; the
+    // targets of a play are narrowed by --hosts and --limit, plays without
+    // any target left are kept empty
+    pub fn plays(&mut self, hosts: Option<&str>, limit: Option<&str>) -> Result<Vec<Play>, Error> {
+        if self.plays.is_empty() {
+            self.select(hosts, limit)?;
+            let mut play = Play { name: None, hosts: None, tasks: None, strategy: Strategy::Parallel, batch: None, stop_on_error: None, targets: std::mem::take(&mut self.targets) };
+            self.resolve_tasks(&mut play.targets)?;
+            return Ok(vec![play])
+        }
+
+        let narrowing = [hosts, limit].iter().flatten()
+            .map(|pattern| Pattern::parse(pattern))
+            .collect::<Result<Vec<Pattern>, Error>>()?;
+
+        let mut plays = std::mem::take(&mut self.plays);
+        for (i, play) in plays.iter_mut().enumerate() {
+            let name = play.name.get_or_insert_with(|| format!("play {}", i + 1)).clone();
+            let in_play = |e: Error| e.play(&name);
+
+            let pattern = Pattern::parse(play.hosts.as_deref().or(self.hosts.as_deref()).unwrap_or("all")).map_err(in_play)?;
+            play.targets = self.targets.iter().filter(|target| pattern.matches(&target.host, &target.groups)).cloned().collect();
+            if play.targets.is_empty() {
+                return Err(Error::new(Kind::Config, format!("no hosts match '{}'", pattern)).play(&name))
+            }
+            for pattern in &narrowing {
+                play.targets.retain(|target| pattern.matches(&target.host, &target.groups));
+            }
+
+            if play.tasks.is_some() {
+                for target in play.targets.iter_mut() {
+                    target.task_lists = play.tasks.clone();
+                }
+            }
+            self.resolve_tasks(&mut play.targets).map_err(in_play)?;
+        }
+
+        if plays.iter().all(|play| play.targets.is_empty()) {
+            return Err(Error::new(Kind::Config, format!("no hosts match '{}'", narrowing.iter().map(|p| p.to_string()).collect::<Vec<String>>().join("' and '"))))
+        }
+
+        Ok(plays)
+    }
+
+    // replace task list names of the targets by the top-level lists and
+    // variables in the commands by their values
+    fn resolve_tasks(&self, targets: &mut [Target]) -> Result<(), Error> {
+        for target in targets.iter_mut() {
+            let mut tasks = Vec::new();
+            let mut stack = Vec::new();
+            match target.task_lists.as_ref().or(self.defaults.tasks.as_ref()) {
+                Some(task_lists) => task_lists.collect(&self.tasks, &self.defaults, &mut stack, &mut tasks),
+                None if self.tasks.contains_key("tasks") => named_tasks(&self.tasks, "tasks", &self.defaults, &mut stack, &mut tasks),
+                None => Err(Error::new(Kind::Config, "no tasks set and no task list named tasks")),
+            }.map_err(|e| e.host(&target.host))?;
+
+            for task in tasks.iter_mut() {
+                task.command = substitute(&task.command, &target.vars, &self.vars).map_err(|e| Error::new(Kind::Config, e).host(&target.host))?;
+            }
+            target.tasks = tasks;
+        }
+
+        Ok(())
+    }
+
+    // fill everything not set in the yaml file from the ssh config
+    pub fn apply_ssh_config(&mut self, ssh_config: &SshConfig) -> Result<(), Error> {
+        let defaults = &self.defaults;
+        for target in self.targets.iter_mut() {
+            let host = ssh_config.lookup(&target.host);
+
+            // the ssh config of a host wins over the config-wide defaults
+            target.hostname = host.hostname;
+            target.user = target.user.take().or(host.user).or_else(|| defaults.user.clone()).or_else(local_user);
+            target.port = target.port.or(host.port).or(defaults.port);
+            target.identity_file = target.identity_file.take().or(host.identity_file);
+
+            if target.jump.is_none() {
+                target.jump = match host.proxy_jump.as_deref() {
+                    None | Some("none") => None,
+                    Some(proxy_jump) => Some(Jump::Chain(proxy_jump.split(',').map(JumpHost::parse).collect::<Result<Vec<JumpHost>, String>>()
+                        .map_err(|e| Error::new(Kind::Config, format!("invalid ProxyJump in ssh config: {}", e)).host(&target.host))?)),
+                };
+            }
+
+            // jump hosts may be ssh config aliases as well
+            if let Some(jump) = target.jump.as_mut() {
+                for hop in jump.hops_mut() {
+                    hop.apply_ssh_config(ssh_config);
+
+                    if let Some(defaults) = &self.ssh_options {
+                        hop.ssh_options = Some(hop.ssh_options.as_ref().map_or(defaults.clone(), |o| o.or(defaults)));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // check settings which can not be checked while parsing
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(ssh_options) = &self.ssh_options {
+            ssh_options.validate().map_err(|e| Error::new(Kind::Config, e))?;
+        }
+
+        // a top-level text is an alias of another task list, one nothing refers
+        // to is most likely a misspelled setting like host_key_cheking: strict
+        let referenced = self.task_list_names();
+        let mut aliases: Vec<(&String, &String)> = self.tasks.iter()
+            .filter_map(|(name, lists)| match lists {
+                TaskLists::Name(alias) if !referenced.contains(&name.as_str()) => Some((name, alias)),
+                _ => None,
+            })
+            .collect();
+        aliases.sort();
+        if let Some((name, alias)) = aliases.first() {
+            return Err(Error::new(Kind::Config, format!("unknown field {} (or unused task list alias of {})", name, alias)))
+        }
+
+        for target in &self.targets {
+            if let Some(ssh_options) = &target.ssh_options {
+                ssh_options.validate().map_err(|e| Error::new(Kind::Config, e).host(&target.host))?;
+            }
+            for hop in target.jump.as_ref().map(Jump::hops).unwrap_or(&[]) {
+                if let Some(ssh_options) = &hop.ssh_options {
+                    ssh_options.validate().map_err(|e| Error::new(Kind::Config, format!("jump host {}: {}", hop, e)).host(&target.host))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // task list names used by the task lists, targets, defaults and plays,
+    // tasks is used by targets without tasks
+    fn task_list_names(&self) -> Vec<&str> {
+        let mut names = vec!["tasks"];
+        let task_lists = self.tasks.values()
+            .chain(self.targets.iter().filter_map(|t| t.task_lists.as_ref()))
+            .chain(self.defaults.tasks.as_ref())
+            .chain(self.plays.iter().filter_map(|p| p.tasks.as_ref()));
+        for task_lists in task_lists {
+            match task_lists {
+                TaskLists::Name(name) => names.push(name),
+                TaskLists::List(items) => names.extend(items.iter().filter_map(|item| match item {
+                    TaskItem::Name(name) => Some(name.as_str()),
+                    _ => None,
+                })),
+            }
+        }
+        names
+    }
+
+    pub fn host_keys(&self) -> HostKeys {
+        HostKeys::new(self.host_key_checking, self.known_hosts.as_deref().map(expand_home))
+    }
+}
+
+impl Play {
+    // true if any target or jump host logs in with the ssh agent
+    pub fn uses_agent(&self) -> bool {
+        self.any_credentials(|credentials| credentials.uses_agent())
+    }
+
+    // true if any target or jump host can log in with nothing but the ssh agent
+    pub fn needs_agent(&self) -> bool {
+        self.any_credentials(|credentials| credentials.needs_agent())
+    }
+
+    fn any_credentials(&self, check: impl Fn(&Credentials) -> bool) -> bool {
+        self.targets.iter().any(|target| {
+            check(&target.credentials()) || target.jump.as_ref().is_some_and(|jump| jump.hops().iter().any(|hop| check(&hop.credentials())))
+        })
+    }
+
+    // every secret reference of the targets and jump hosts must be readable
+    // from its store before anything is connected
+    pub fn check_secrets(&self, authenticator: &impl Authenticator) -> Result<(), Error> {
+        for target in &self.targets {
+            let hops = target.jump.as_ref().map(Jump::hops).unwrap_or(&[]);
+            for credentials in std::iter::once(target.credentials()).chain(hops.iter().map(JumpHost::credentials)) {
+                let secrets = [("password", credentials.password), ("identity_passphrase", credentials.identity_passphrase), ("totp_secret", credentials.totp_secret)];
+                for (field, secret) in secrets.iter() {
+                    if let Some(Secret::Reference(reference)) = secret {
+                        authenticator.check(reference).map_err(|e| Error::new(Kind::Config, format!("{} from {}: {}", field, reference, e)).host(credentials.host))?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // hosts at the same time
+    pub fn batch_size(&self) -> usize {
+        match self.strategy {
+            Strategy::Parallel => self.targets.len().max(1),
+            Strategy::Serial => self.batch.unwrap_or(1).max(1),
+        }
+    }
+
+    pub fn stops_on_error(&self) -> bool {
+        self.stop_on_error.unwrap_or(true)
+    }
+
+    pub fn is_named(&self) -> bool {
+        self.name.is_some()
+    }
+}
+
 impl Target {
-    pub fn connect(&self, authenticator: &Option<impl Authenticator>, resolver: &Option<impl Resolver>) -> Result<Session, Box<dyn Error>> {
-        // try resolver
-        let address = match resolver {
-            // always fall back to dns name
-            Some(resolver) => {
-                match resolver.get(&self.host) {
-                    Ok(ip) => {
-                        info!("found ip address: {}", ip);
-                        ip
+    pub fn connect(&self, authenticator: &impl Authenticator, resolver: &Option<impl Resolver>, tunnels: &Tunnels, host_keys: &HostKeys) -> Result<Session, Error> {
+        let address = match &self.ip {
+            Some(ip) => ip.to_string(),
+            None => resolve(self.hostname.as_ref().unwrap_or(&self.host), resolver),
+        };
+        let port = self.port.unwrap_or(22u16);
+        let timeout = Duration::from_secs(self.connect_timeout.unwrap_or(10));
+
+        // Open SSH Session to Address, optionally through the jump hosts
+        let tcp = match self.jump.as_ref().map(Jump::hops).unwrap_or(&[]) {
+            [] => net::connect(&address, port, timeout),
+            hops => tunnels.connect(hops, &address, port, timeout, authenticator, resolver, host_keys),
+        };
+
+        login(tcp.map_err(|e| e.host(&self.host))?, &self.credentials(), authenticator, host_keys)
+    }
+
+    fn credentials(&self) -> Credentials<'_> {
+        Credentials {
+            host: &self.host,
+            known_host: self.hostname.as_deref().unwrap_or(&self.host),
+            port: self.port.unwrap_or(22u16),
+            user: self.user.as_deref().unwrap_or_default(),
+            password: &self.password,
+            identity_file: &self.identity_file,
+            identity_passphrase: &self.identity_passphrase,
+            totp_secret: &self.totp_secret,
+            auth: &self.auth,
+            ssh_options: &self.ssh_options,
+        }
+    }
+}
+
+impl Jump {
+    pub fn hops(&self) -> &[JumpHost] {
+        match self {
+            Jump::Chain(hops) => hops,
+            Jump::Hop(hop) => std::slice::from_ref(hop),
+        }
+    }
+
+    fn hops_mut(&mut self) -> &mut [JumpHost] {
+        match self {
+            Jump::Chain(hops) => hops,
+            Jump::Hop(hop) => std::slice::from_mut(hop),
+        }
+    }
+}
+
+impl JumpHost {
+    // parse a jump host written as [user@]host[:port]
+    pub fn parse(s: &str) -> Result<Self, String> {
+        JumpHost::try_from(JumpSpec::Short(s.to_string()))
+    }
+
+    fn apply_ssh_config(&mut self, ssh_config: &SshConfig) {
+        let host = ssh_config.lookup(&self.host);
+
+        self.hostname = host.hostname;
+        self.user = self.user.take().or(host.user).or_else(local_user);
+        self.port = self.port.or(host.port);
+        self.identity_file = self.identity_file.take().or(host.identity_file);
+    }
+
+    pub fn address(&self, resolver: &Option<impl Resolver>) -> String {
+        resolve(self.hostname.as_ref().unwrap_or(&self.host), resolver)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port.unwrap_or(22u16)
+    }
+
+    pub fn login(&self, tcp: TcpStream, authenticator: &impl Authenticator, host_keys: &HostKeys) -> Result<Session, Error> {
+        login(tcp, &self.credentials(), authenticator, host_keys)
+    }
+
+    fn credentials(&self) -> Credentials<'_> {
+        Credentials {
+            host: &self.host,
+            known_host: self.hostname.as_deref().unwrap_or(&self.host),
+            port: self.port.unwrap_or(22u16),
+            user: self.user.as_deref().unwrap_or_default(),
+            password: &self.password,
+            identity_file: &self.identity_file,
+            identity_passphrase: &self.identity_passphrase,
+            totp_secret: &self.totp_secret,
+            auth: &self.auth,
+            ssh_options: &self.ssh_options,
+        }
+    }
+}
+
+impl fmt::Display for JumpHost {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{}@{}:{}", user, self.host, self.port()),
+            None => write!(f, "{}:{}", self.host, self.port()),
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{}@{}:{}", user, self.host, self.port.unwrap_or(22u16)),
+            None => write!(f, "{}:{}", self.host, self.port.unwrap_or(22u16)),
+        }
+    }
+}
+
+impl fmt::Display for Play {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("play"))?;
+        match self.strategy {
+            Strategy::Parallel => write!(f, " ({} hosts)", self.targets.len()),
+            Strategy::Serial => write!(f, " ({} hosts, {} at a time)", self.targets.len(), self.batch_size()),
+        }
+    }
+}
+
+// first line of the command with the expected result
+impl fmt::Display for Task {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = self.command.trim().lines();
+        write!(f, "{}", lines.next().unwrap_or_default())?;
+        if lines.next().is_some() {
+            write!(f, " ...")?;
+        }
+        write!(f, " (expected {}", self.expected_result)?;
+        if self.stop_on_error {
+            write!(f, ", stop on error")?;
+        }
+        if self.r#become {
+            write!(f, ", become")?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl Defaults {
+    // fill everything not set from the defaults of an inventory or an import
+    fn merge(&mut self, other: Defaults) {
+        self.user = self.user.take().or(other.user);
+        self.port = self.port.or(other.port);
+        self.auth = self.auth.take().or(other.auth);
+        self.connect_timeout = self.connect_timeout.or(other.connect_timeout);
+        self.shell = self.shell.or(other.shell);
+        self.tasks = self.tasks.take().or(other.tasks);
+        self.expected_result = self.expected_result.or(other.expected_result);
+        self.stop_on_error = self.stop_on_error.or(other.stop_on_error);
+        self.r#become = self.r#become.or(other.r#become);
+    }
+}
+
+impl TaskSpec {
+    // expected_result and stop_on_error must be set here or in the defaults
+    fn resolve(&self, defaults: &Defaults) -> Result<Task, Error> {
+        let missing = |field: &str| Error::new(Kind::Config, format!("task '{}' has no {} and there is no default", self.command.trim(), field));
+        Ok(Task {
+            command: self.command.clone(),
+            expected_result: self.expected_result.or(defaults.expected_result).ok_or_else(|| missing("expected_result"))?,
+            stop_on_error: self.stop_on_error.or(defaults.stop_on_error).ok_or_else(|| missing("stop_on_error"))?,
+            r#become: self.r#become.or(defaults.r#become).unwrap_or(false),
+        })
+    }
+}
+
+// written like the spec, the parsed fields are internal
+impl JsonSchema for JumpHost {
+    fn schema_name() -> String {
+        "JumpHost".to_string()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        JumpSpec::json_schema(gen)
+    }
+}
+
+impl TryFrom<JumpSpec> for JumpHost {
+    type Error = String;
+
+    fn try_from(spec: JumpSpec) -> Result<Self, Self::Error> {
+        let (host, port, user, password, identity_file, identity_passphrase, totp_secret, auth, ssh_options) = match spec {
+            JumpSpec::Full(fields) => {
+                let JumpFields { host, port, user, password, identity_file, identity_passphrase, totp_secret, auth, ssh_options } = *fields;
+                (host, port, user, password, identity_file, identity_passphrase, totp_secret, auth, ssh_options)
+            },
+            JumpSpec::Short(s) => {
+                let (user, rest) = match s.rsplit_once('@') {
+                    Some((user, rest)) => (Some(user.to_string()), rest),
+                    None => (None, s.as_str()),
+                };
+
+                // ipv6 addresses must be written in brackets if a port is given
+                let (host, port) = match rest.strip_prefix('[') {
+                    Some(bracketed) => match bracketed.split_once(']') {
+                        Some((host, port)) => (host, port.strip_prefix(':')),
+                        None => return Err(format!("invalid jump host '{}'", s)),
+                    },
+                    None => match rest.matches(':').count() {
+                        1 => match rest.split_once(':') {
+                            Some((host, port)) => (host, Some(port)),
+                            None => (rest, None),
+                        },
+                        _ => (rest, None),
                     },
-                    Err(e) => {
-                        error!("unable to resolve ip: {}", e);
-                        self.host.to_string()
+                };
+
+                let port = match port {
+                    Some(p) => Some(p.parse::<u16>().map_err(|_| format!("invalid port in jump host '{}'", s))?),
+                    None => None,
+                };
+
+                (host.to_string(), port, user, None, None, None, None, None, None)
+            },
+        };
+
+        Ok(JumpHost { host, hostname: None, port, user, password, identity_file, identity_passphrase, totp_secret, auth, ssh_options })
+    }
+}
+
+// same as ssh, use the local username if none is given
+fn local_user() -> Option<String> {
+    env::var("USER").ok()
+}
+
+impl TaskLists {
+    // append all tasks, names are looked up in the named task lists
+    fn collect(&self, lists: &HashMap<String, TaskLists>, defaults: &Defaults, stack: &mut Vec<String>, tasks: &mut Vec<Task>) -> Result<(), Error> {
+        match self {
+            TaskLists::Name(name) => named_tasks(lists, name, defaults, stack, tasks),
+            TaskLists::List(items) => {
+                for item in items {
+                    match item {
+                        TaskItem::Name(name) => named_tasks(lists, name, defaults, stack, tasks)?,
+                        TaskItem::Task(task) => tasks.push(task.resolve(defaults)?),
+                        TaskItem::Include { include } => return Err(Error::new(Kind::Config, format!("include {} was not read", include.display()))),
                     }
                 }
+                Ok(())
             },
-            None => self.host.to_string(),
+        }
+    }
+}
+
+// stack holds the names being resolved to find lists containing themselves
+fn named_tasks(lists: &HashMap<String, TaskLists>, name: &str, defaults: &Defaults, stack: &mut Vec<String>, tasks: &mut Vec<Task>) -> Result<(), Error> {
+    if stack.iter().any(|n| n == name) {
+        return Err(Error::new(Kind::Config, format!("task list {} contains itself ({} -> {})", name, stack.join(" -> "), name)))
+    }
+
+    let list = match lists.get(name) {
+        Some(list) => list,
+        None => {
+            let mut names: Vec<&str> = lists.keys().map(String::as_str).collect();
+            names.sort_unstable();
+            return Err(Error::new(Kind::Config, format!("unknown task list '{}', defined are: {}", name, names.join(", "))))
+        },
+    };
+
+    stack.push(name.to_string());
+    list.collect(lists, defaults, stack, tasks)?;
+    stack.pop();
+
+    Ok(())
+}
+
+// replace {{ name }} by the variable of the target or the config, other
+// braces are kept as they are (e.g. docker ps --format '{{.Names}}')
+fn substitute(command: &str, vars: &HashMap<String, String>, defaults: &HashMap<String, String>) -> Result<String, String> {
+    let mut result = String::new();
+    let mut rest = command;
+
+    while let Some(start) = rest.find("{{") {
+        let end = match rest[start..].find("}}") {
+            Some(end) => start + end + 2,
+            None => break,
         };
+        let name = rest[start + 2..end - 2].trim();
 
-        // Open SSH Session to Address
-        match TcpStream::connect(format!("{}:{}", address, self.port.unwrap_or(22u16)).as_str()) {
-            Ok(tcp) => {
-                match Session::new() {
-                    Ok(mut session) => {
-                        session.set_timeout(150000);
-                        session.set_tcp_stream(tcp);
-                        session.handshake()?;
-                        match &self.password {
-                            Some(password) => {
-                                match password.as_str() {
-                                    "bitwarden" => {
-                                        match authenticator {
-                                            Some(a) => session.userauth_password(&self.user, a.get(&self.host, &self.user)?)?,
-                                            None => return Err(Box::new(ssh2::Error::new(ssh2::ErrorCode::Session(-18), "authentication method not available")))
-                                        }
-                                    },
-                                    _ => session.userauth_password(&self.user, password)?,
-                                }
-                            },
-                            None => session.userauth_agent(&self.user)?,
-                        }
-                        
-                        Ok(session)
-                    },
-                    Err(e) => {
-                        error!("Connection Error: {}", e);
-                        return Err(Box::new(ssh2::Error::new(ssh2::ErrorCode::Session(-26), "Connection Error")))
-                    }
+        result.push_str(&rest[..start]);
+        if !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            match vars.get(name).or_else(|| defaults.get(name)) {
+                Some(value) => result.push_str(value),
+                None => return Err(format!("undefined variable {} in command '{}'", name, command)),
+            }
+        } else {
+            result.push_str(&rest[start..end]);
+        }
+        rest = &rest[end..];
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+// try resolver, always fall back to dns name
+fn resolve(host: &str, resolver: &Option<impl Resolver>) -> String {
+    match resolver {
+        Some(resolver) => {
+            match resolver.get(host) {
+                Ok(ip) => {
+                    info!("found ip address: {}", ip);
+                    ip
+                },
+                Err(e) => {
+                    error!("unable to resolve ip: {}", e);
+                    host.to_string()
                 }
+            }
+        },
+        None => host.to_string(),
+    }
+}
+
+impl Credentials<'_> {
+    fn uses_agent(&self) -> bool {
+        self.methods().contains(&AuthMethod::Agent)
+    }
+
+    // keyboard-interactive is only in the chain if it was configured or a
+    // password or totp secret is given, key and password need their secret
+    fn needs_agent(&self) -> bool {
+        self.uses_agent() && self.methods().iter().all(|method| match method {
+            AuthMethod::Agent => true,
+            AuthMethod::Key => self.identity_file.is_none(),
+            AuthMethod::Password => self.password.is_none(),
+            AuthMethod::KeyboardInteractive => false,
+        })
+    }
+
+    fn methods(&self) -> Vec<AuthMethod> {
+        self.methods_with(env::var_os("SSH_AUTH_SOCK").is_some())
+    }
+
+    // configured methods or every method with a given secret in the order of
+    // openssh, the agent if it is running or there is nothing else to try
+    fn methods_with(&self, agent_running: bool) -> Vec<AuthMethod> {
+        if let Some(auth) = self.auth {
+            return auth.clone()
+        }
+
+        let mut methods = Vec::new();
+        if agent_running || (self.identity_file.is_none() && self.password.is_none() && self.totp_secret.is_none()) {
+            methods.push(AuthMethod::Agent);
+        }
+        if self.identity_file.is_some() {
+            methods.push(AuthMethod::Key);
+        }
+        if self.password.is_some() {
+            methods.push(AuthMethod::Password);
+        }
+        if self.password.is_some() || self.totp_secret.is_some() {
+            methods.push(AuthMethod::KeyboardInteractive);
+        }
+        methods
+    }
+}
+
+impl AuthMethod {
+    // method name announced by the server
+    fn ssh_name(&self) -> &'static str {
+        match self {
+            AuthMethod::Agent | AuthMethod::Key => "publickey",
+            AuthMethod::Password => "password",
+            AuthMethod::KeyboardInteractive => "keyboard-interactive",
+        }
+    }
+}
+
+impl fmt::Display for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthMethod::Agent => write!(f, "agent"),
+            AuthMethod::Key => write!(f, "key"),
+            AuthMethod::Password => write!(f, "password"),
+            AuthMethod::KeyboardInteractive => write!(f, "keyboard-interactive"),
+        }
+    }
+}
+
+// handshake and authenticate a ssh session on an open stream
+fn login(tcp: TcpStream, credentials: &Credentials, authenticator: &impl Authenticator, host_keys: &HostKeys) -> Result<Session, Error> {
+    let mut session = Session::new().context(Kind::Handshake, "unable to create session").map_err(|e| e.host(credentials.host))?;
+
+    session.set_timeout(150000);
+    if let Some(ssh_options) = credentials.ssh_options {
+        ssh_options.apply(&session).map_err(|e| e.host(credentials.host))?;
+    }
+    session.set_tcp_stream(tcp);
+    session.handshake().context(Kind::Handshake, "ssh handshake failed").map_err(|e| e.host(credentials.host))?;
+
+    // never send credentials to an unverified server
+    host_keys.verify(&session, credentials.known_host, credentials.port).map_err(|e| e.host(credentials.host))?;
+
+    authenticate(&session, credentials, authenticator).map_err(|e| e.host(credentials.host))?;
+
+    Ok(session)
+}
+
+// try the authentication methods in order, skip the ones the server does not offer
+fn authenticate(session: &Session, credentials: &Credentials, authenticator: &impl Authenticator) -> Result<(), Error> {
+    let mut failures: Vec<String> = Vec::new();
+
+    for method in credentials.methods() {
+        // offered methods may change after a partial success (e.g. key and otp)
+        let offered = session.auth_methods(credentials.user).context(Kind::Auth, "unable to get authentication methods")?.to_string();
+        if !offered.split(',').any(|m| m == method.ssh_name()) {
+            failures.push(format!("{} not offered by server", method));
+            continue;
+        }
+
+        let result = match method {
+            AuthMethod::Agent => session.userauth_agent(credentials.user).context(Kind::Auth, "agent"),
+            AuthMethod::Key => match credentials.identity_file {
+                Some(identity_file) => {
+                    let passphrase = match credentials.identity_passphrase {
+                        Some(passphrase) => Some(reveal(passphrase, &key_name(identity_file), credentials.user, authenticator)?),
+                        None => None,
+                    };
+                    userauth_key(session, credentials.user, identity_file, passphrase)
+                },
+                None => Err(Error::new(Kind::Auth, "no identity_file set")),
+            },
+            AuthMethod::Password => match credentials.password {
+                Some(password) => session.userauth_password(credentials.user, &reveal(password, credentials.host, credentials.user, authenticator)?).context(Kind::Auth, "password"),
+                None => Err(Error::new(Kind::Auth, "no password set")),
+            },
+            AuthMethod::KeyboardInteractive => {
+                let password = match credentials.password {
+                    Some(password) => Some(reveal(password, credentials.host, credentials.user, authenticator)?),
+                    None => None,
+                };
+                let totp_secret = match credentials.totp_secret {
+                    Some(secret) => Some(reveal(secret, credentials.host, credentials.user, authenticator)?),
+                    None => None,
+                };
+                let mut prompter = Prompter {
+                    host: credentials.host,
+                    password,
+                    totp_secret,
+                };
+                session.userauth_keyboard_interactive(credentials.user, &mut prompter).context(Kind::Auth, "keyboard-interactive")
             },
+        };
+
+        match result {
+            Ok(()) if session.authenticated() => {
+                info!("authenticated as {} with {}", credentials.user, method);
+                return Ok(());
+            },
+            Ok(()) => info!("partial authentication as {} with {}", credentials.user, method),
             Err(e) => {
-                error!("Connection Error: {} {}", format!("{}:{}", address, self.port.unwrap_or(22u16)).as_str(), e);
-                return Err(Box::new(ssh2::Error::new(ErrorCode::Session(-9), "Connection Error")))
-            }
+                warn!("authentication as {} with {} failed: {}", credentials.user, method, e);
+                failures.push(e.to_string());
+            },
+        }
+    }
+
+    Err(Error::new(Kind::Auth, format!("no method succeeded for user {} ({})", credentials.user, failures.join(", "))))
+}
+
+// references are read from their secret store and hidden in the log
+fn reveal(secret: &Secret, name: &str, user: &str, authenticator: &impl Authenticator) -> Result<String, Error> {
+    match secret {
+        Secret::Literal(secret) => Ok(secret.to_string()),
+        Secret::Reference(reference) => {
+            let secret = authenticator.get(reference, name, user).map_err(|e| Error::new(Kind::Auth, format!("{} lookup for {} failed: {}", reference, name, e)))?;
+            logger::redact(&secret);
+            Ok(secret)
+        },
+    }
+}
+
+// replace includes by the items of the included files
+fn include(items: &mut Vec<TaskItem>, path: &Path, stack: &mut Vec<PathBuf>) -> Result<(), Error> {
+    let mut resolved = Vec::new();
+    for item in items.drain(..) {
+        match item {
+            TaskItem::Include { include: included } => {
+                let file = relative(path, &included);
+                enter(stack, &file)?;
+                let mut items: Vec<TaskItem> = format::load(&file)?;
+                include(&mut items, &file, stack)?;
+                stack.pop();
+                resolved.append(&mut items);
+            },
+            item => resolved.push(item),
         }
     }
+    *items = resolved;
+    Ok(())
+}
+
+// a path relative to the directory of the file containing it
+fn relative(base: &Path, path: &Path) -> PathBuf {
+    match base.parent() {
+        Some(directory) => directory.join(expand_home(path)),
+        None => expand_home(path),
+    }
+}
+
+// the same file is found through different relative paths
+fn file_id(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+// files being read, a file reading itself again is a cycle
+fn enter(stack: &mut Vec<PathBuf>, path: &Path) -> Result<(), Error> {
+    let id = file_id(path);
+    if let Some(start) = stack.iter().position(|p| *p == id) {
+        let chain: Vec<String> = stack[start..].iter().chain(std::iter::once(&id)).map(|p| p.display().to_string()).collect();
+        return Err(Error::new(Kind::Config, format!("{} includes itself ({})", path.display(), chain.join(" -> "))))
+    }
+    stack.push(id);
+    Ok(())
+}
+
+// file name of a key is used to look up its passphrase
+fn key_name(identity_file: &Path) -> String {
+    identity_file.file_name().map(|n| n.to_string_lossy().to_string()).unwrap_or_default()
+}
+
+// expand ~ to the home directory like ssh does
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var("HOME")) {
+        (Ok(rest), Ok(home)) => Path::new(&home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+fn userauth_key(session: &Session, user: &str, identity_file: &Path, passphrase: Option<String>) -> Result<(), Error> {
+    let private_key = expand_home(identity_file);
+
+    let metadata = private_key.metadata().context(Kind::Auth, &format!("identity file {} not readable", private_key.display()))?;
+
+    // same check as ssh, a key readable by others is refused
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = metadata.permissions().mode() & 0o777;
+        if mode & 0o077 != 0 {
+            return Err(Error::new(Kind::Auth, format!("permissions {:04o} for identity file {} are too open", mode, private_key.display())));
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = metadata;
+
+    // public key is derived from the private key if there is no .pub file
+    let mut public_key = private_key.clone().into_os_string();
+    public_key.push(".pub");
+    let public_key = PathBuf::from(public_key);
+    let public_key = match public_key.exists() {
+        true => Some(public_key.as_path()),
+        false => None,
+    };
+
+    match session.userauth_pubkey_file(user, public_key, &private_key, passphrase.as_deref()) {
+        Ok(()) => Ok(()),
+        // LIBSSH2_ERROR_FILE
+        Err(e) if e.code() == ErrorCode::Session(-16) => match passphrase {
+            Some(_) => Err(Error::new(Kind::Auth, format!("wrong passphrase or invalid key format for identity file {}", private_key.display()))),
+            None => Err(Error::new(Kind::Auth, format!("identity file {} is encrypted or has an invalid key format, set identity_passphrase", private_key.display()))),
+        },
+        // LIBSSH2_ERROR_AUTHENTICATION_FAILED, LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED
+        Err(e) if e.code() == ErrorCode::Session(-18) || e.code() == ErrorCode::Session(-19) => {
+            Err(Error::new(Kind::Auth, format!("identity file {} was not accepted for user {}", private_key.display(), user)))
+        },
+        Err(e) => Err(Error::new(Kind::Auth, format!("identity file {}", private_key.display())).source(e)),
+    }
 }
 
 impl Task {
-    pub fn run(&self, session: &Session) -> Result<State, Box<dyn Error>> {
+    pub fn run(&self, session: &Session) -> Result<State, Error> {
         // Run command in session
-        let mut channel = session.channel_session()?;
+        let mut channel = session.channel_session().context(Kind::Channel, "unable to open channel")?;
         
         // Add stderr stream to normal output
-        channel.handle_extended_data(ExtendedData::Merge)?;
+        channel.handle_extended_data(ExtendedData::Merge).context(Kind::Channel, "unable to merge stderr")?;
 
-        channel.exec(&self.command)?;
+        channel.exec(&self.remote_command()).context(Kind::Channel, "unable to execute command")?;
         
-        let mut buffer = String::new();
+        let mut output = Vec::new();
 
         // catch session timeout
-        match channel.read_to_string(&mut buffer) {
-            Err(_e) => {
-                return Err(Box::new(ssh2::Error::new(ErrorCode::Session(-23), "Data Read Error/Timeout")))
-            },
-            _ => ()
+        if let Err(e) = sshoptions::read_to_end(session, &mut channel, &mut output) {
+            return match e.kind() {
+                std::io::ErrorKind::TimedOut => Err(Error::new(Kind::Timeout, "no output received in time").source(e)),
+                _ => Err(Error::new(Kind::Channel, "unable to read output").source(e)),
+            }
         }
 
-        channel.wait_close()?;
+        channel.wait_close().context(Kind::Channel, "unable to close channel")?;
+
+        let buffer = String::from_utf8_lossy(&output);
+
+        let r = channel.exit_status().context(Kind::Channel, "unable to get exit status")?;
+        self.evaluate(&buffer, r)
+    }
+
+    pub fn run_in(&self, shell: &mut Shell) -> Result<State, Error> {
+        // Run command in persistent shell
+        let (buffer, r) = shell.exec(&self.remote_command())?;
+
+        self.evaluate(&buffer, r)
+    }
 
+    // become runs the command in a root shell, sudo must not ask for a password
+    fn remote_command(&self) -> String {
+        match self.r#become {
+            true => format!("sudo -n -- sh -c '{}'", self.command.replace('\'', "'\\''")),
+            false => self.command.clone(),
+        }
+    }
+
+    fn evaluate(&self, buffer: &str, r: i32) -> Result<State, Error> {
         // write output to logfile
         info!("{}", buffer);
 
-        match channel.exit_status() {
-            Ok(r) => {
-                if self.expected_result == r {
-                    return Ok(State::Ok);
-                } else {
-                    if self.stop_on_error == true {
-                        error!("expected result {} but recieved {}", self.expected_result, r);
-                        return Ok(State::Failed);
-                    } else {
-                        warn!("expected result {} but recieved {}", self.expected_result, r);
-                        return Ok(State::Warning);
-                    }
-                }
-            },
-            Err(e) => {
-                error!("{}", e);
-                return Err(Box::new(e));
-            }
+        if self.expected_result == r {
+            Ok(State::Ok)
+        } else if self.stop_on_error {
+            Err(Error::new(Kind::Assertion, format!("expected result {} but recieved {}", self.expected_result, r)))
+        } else {
+            warn!("expected result {} but recieved {}", self.expected_result, r);
+            Ok(State::Warning)
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+    }
+
+    fn credentials<'a>(auth: &'a Option<Vec<AuthMethod>>, identity_file: &'a Option<PathBuf>, password: &'a Option<Secret>, totp_secret: &'a Option<Secret>) -> Credentials<'a> {
+        Credentials { host: "h1", known_host: "h1", port: 22, user: "root", password, identity_file, identity_passphrase: &None, totp_secret, auth, ssh_options: &None }
+    }
+
+    #[test]
+    fn methods_without_secrets_use_the_agent() {
+        let credentials = credentials(&None, &None, &None, &None);
+        assert_eq!(credentials.methods_with(true), [AuthMethod::Agent]);
+        assert_eq!(credentials.methods_with(false), [AuthMethod::Agent]);
+    }
+
+    #[test]
+    fn methods_in_openssh_order() {
+        let identity_file = Some(PathBuf::from("~/.ssh/id_ed25519"));
+        let password = Some(Secret::Literal("secret".to_string()));
+        let credentials = credentials(&None, &identity_file, &password, &None);
+        assert_eq!(credentials.methods_with(true), [AuthMethod::Agent, AuthMethod::Key, AuthMethod::Password, AuthMethod::KeyboardInteractive]);
+        assert_eq!(credentials.methods_with(false), [AuthMethod::Key, AuthMethod::Password, AuthMethod::KeyboardInteractive]);
+    }
+
+    #[test]
+    fn methods_with_totp_secret() {
+        let totp_secret = Some(Secret::Literal("GEZDGNBV".to_string()));
+        assert_eq!(credentials(&None, &None, &None, &totp_secret).methods_with(false), [AuthMethod::KeyboardInteractive]);
+    }
+
+    #[test]
+    fn configured_methods_win() {
+        let auth = Some(vec![AuthMethod::Password, AuthMethod::Agent]);
+        let identity_file = Some(PathBuf::from("key"));
+        assert_eq!(credentials(&auth, &identity_file, &None, &None).methods_with(true), [AuthMethod::Password, AuthMethod::Agent]);
+    }
+
+    #[test]
+    fn agent_is_needed_without_fallback() {
+        let password = Some(Secret::Literal("secret".to_string()));
+        let only_agent = Some(vec![AuthMethod::Agent, AuthMethod::Password]);
+        assert!(credentials(&None, &None, &None, &None).needs_agent());
+        assert!(credentials(&only_agent, &None, &None, &None).needs_agent());
+        assert!(!credentials(&only_agent, &None, &password, &None).needs_agent());
+        assert!(!credentials(&Some(vec![AuthMethod::Agent, AuthMethod::KeyboardInteractive]), &None, &None, &None).needs_agent());
+    }
+
+    #[test]
+    fn unused_task_list_alias_is_refused() {
+        let config: Config = Format::Yaml.parse("host_key_cheking: strict\nused: t\nt: []\ntargets:\n  - host: h1\n    tasks: [used]\n").unwrap();
+        assert_eq!(config.validate().unwrap_err().to_string(), "invalid config: unknown field host_key_cheking (or unused task list alias of strict)");
+
+        let config: Config = Format::Yaml.parse("tasks: t\nt: []\ntargets:\n  - host: h1\n").unwrap();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn substitute_target_before_config_vars() {
+        let target = vars(&[("version", "2.1")]);
+        let config = vars(&[("version", "1.0"), ("package", "nginx")]);
+        assert_eq!(substitute("apt install {{ package }}={{version}}", &target, &config).unwrap(), "apt install nginx=2.1");
+    }
+
+    #[test]
+    fn substitute_keeps_other_braces() {
+        let none = HashMap::new();
+        assert_eq!(substitute("docker ps --format '{{.Names}}'", &none, &none).unwrap(), "docker ps --format '{{.Names}}'");
+        assert_eq!(substitute("echo {{ }} {{ a b }} {single}", &none, &none).unwrap(), "echo {{ }} {{ a b }} {single}");
+        assert_eq!(substitute("echo {{ open", &none, &none).unwrap(), "echo {{ open");
+    }
+
+    #[test]
+    fn substitute_refuses_undefined_variables() {
+        let none = HashMap::new();
+        assert_eq!(substitute("echo {{ missing }}", &none, &none).unwrap_err(), "undefined variable missing in command 'echo {{ missing }}'");
+    }
+
+    #[test]
+    fn substitute_does_not_expand_values() {
+        let target = vars(&[("a", "{{ b }}"), ("b", "x")]);
+        assert_eq!(substitute("{{a}}{{b}}", &target, &HashMap::new()).unwrap(), "{{ b }}x");
+    }
+}
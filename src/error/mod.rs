@@ -0,0 +1,146 @@
+use std::error;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    // config file can not be read or is invalid
+    Config,
+    // host name can not be resolved
+    Resolve,
+    // tcp connection or jump host tunnel failed
+    Connect,
+    // ssh handshake failed
+    Handshake,
+    // host key is unknown or can not be checked
+    HostKey,
+    // host key differs from the one in known_hosts
+    HostKeyMismatch,
+    // no authentication method succeeded
+    Auth,
+    // channel, shell or command execution failed
+    Channel,
+    // no data received in time
+    Timeout,
+    // command returned an unexpected result and stop_on_error is set
+    Assertion,
+}
+
+impl Kind {
+    // short text for the progress bar
+    pub fn label(&self) -> &'static str {
+        match self {
+            Kind::Config => "invalid config",
+            Kind::Resolve => "resolve failed",
+            Kind::Connect => "connection failed",
+            Kind::Handshake => "handshake failed",
+            Kind::HostKey => "host key check failed",
+            Kind::HostKeyMismatch => "host key mismatch",
+            Kind::Auth => "authentication failed",
+            Kind::Channel => "channel failed",
+            Kind::Timeout => "timeout",
+            Kind::Assertion => "command failed",
+        }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Kind::Config => 2,
+            Kind::Resolve => 3,
+            Kind::Connect => 4,
+            Kind::Handshake => 5,
+            Kind::HostKey | Kind::HostKeyMismatch => 6,
+            Kind::Auth => 7,
+            Kind::Channel => 8,
+            Kind::Timeout => 9,
+            Kind::Assertion => 10,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: Kind,
+    play: Option<String>,
+    host: Option<String>,
+    task: Option<usize>,
+    message: String,
+    source: Option<Box<dyn error::Error + Send + Sync>>,
+}
+
+impl Error {
+    pub fn new(kind: Kind, message: impl Into<String>) -> Self {
+        Error {
+            kind,
+            play: None,
+            host: None,
+            task: None,
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn kind(&self) -> Kind {
+        self.kind
+    }
+
+    // host is only set once, the innermost one (e.g. a jump host) is kept
+    pub fn host(mut self, host: &str) -> Self {
+        if self.host.is_none() {
+            self.host = Some(host.to_string());
+        }
+        self
+    }
+
+    // name of the play of a playbook with several plays
+    pub fn play(mut self, play: &str) -> Self {
+        self.play = Some(play.to_string());
+        self
+    }
+
+    // task number starting at 1
+    pub fn task(mut self, task: usize) -> Self {
+        self.task = Some(task);
+        self
+    }
+
+    pub fn source(mut self, source: impl Into<Box<dyn error::Error + Send + Sync>>) -> Self {
+        self.source = Some(source.into());
+        self
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(play) = &self.play {
+            write!(f, "{}: ", play)?;
+        }
+        if let Some(host) = &self.host {
+            write!(f, "{}: ", host)?;
+        }
+        if let Some(task) = self.task {
+            write!(f, "task {}: ", task)?;
+        }
+        write!(f, "{}: {}", self.kind.label(), self.message)?;
+        if let Some(source) = &self.source {
+            write!(f, ": {}", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        self.source.as_ref().map(|e| e.as_ref() as &(dyn error::Error + 'static))
+    }
+}
+
+// wrap any error with a kind and a short description
+pub trait Context<T> {
+    fn context(self, kind: Kind, message: &str) -> Result<T, Error>;
+}
+
+impl<T, E: Into<Box<dyn error::Error + Send + Sync>>> Context<T> for Result<T, E> {
+    fn context(self, kind: Kind, message: &str) -> Result<T, Error> {
+        self.map_err(|e| Error::new(kind, message).source(e))
+    }
+}
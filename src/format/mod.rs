@@ -0,0 +1,105 @@
+use std::fmt;
+use std::fs;
+use std::io::{self, Read};
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::de::DeserializeOwned;
+
+use crate::error::{Context, Error, Kind};
+
+use crate::interpolate;
+
+// file formats of config and inventory files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Yaml,
+    Json,
+    Toml,
+}
+
+// a deserialization error, position as line and column (both start at 1)
+#[derive(Debug)]
+pub struct ParseError {
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl Format {
+    // from the file extension, yaml for everything else (and stdin)
+    pub fn detect(path: &Path) -> Format {
+        match path.extension().and_then(|e| e.to_str()).map(str::to_lowercase).as_deref() {
+            Some("json") => Format::Json,
+            Some("toml") => Format::Toml,
+            _ => Format::Yaml,
+        }
+    }
+
+    pub fn parse<T: DeserializeOwned>(&self, source: &str) -> Result<T, ParseError> {
+        match self {
+            Format::Yaml => serde_yaml::from_str(source).map_err(|e| ParseError {
+                position: e.location().map(|l| (l.line(), l.column())),
+                message: e.to_string().split(" at line ").next().unwrap_or_default().to_string(),
+            }),
+            Format::Json => serde_json::from_str(source).map_err(|e| ParseError {
+                position: Some((e.line(), e.column())).filter(|(line, _)| *line > 0),
+                message: e.to_string().split(" at line ").next().unwrap_or_default().to_string(),
+            }),
+            Format::Toml => toml::from_str(source).map_err(|e| ParseError {
+                position: e.line_col().map(|(line, column)| (line + 1, column + 1)),
+                message: e.to_string().split(" at line ").next().unwrap_or_default().to_string(),
+            }),
+        }
+    }
+
+    // references like ${NAME} are expanded after the fields are checked, so
+    // errors still point to the position in the file
+    pub fn parse_expanded<T: DeserializeOwned>(&self, path: &Path, source: &str) -> Result<T, Error> {
+        let located = |e: &dyn fmt::Display| Error::new(Kind::Config, format!("{}: {}", path.display(), e));
+        self.parse::<T>(source).map_err(|e| located(&e))?;
+
+        let mut value: serde_json::Value = self.parse(source).map_err(|e| located(&e))?;
+        interpolate::expand(&mut value).map_err(|e| located(&e))?;
+        serde_json::from_value(value).map_err(|e| located(&e))
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "yaml" | "yml" => Ok(Format::Yaml),
+            "json" => Ok(Format::Json),
+            "toml" => Ok(Format::Toml),
+            _ => Err(format!("unknown format {}, use yaml, json or toml", s)),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{} at line {} column {}", self.message, line, column),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// a file in the format of its extension
+pub fn load<T: DeserializeOwned>(path: &Path) -> Result<T, Error> {
+    Format::detect(path).parse_expanded(path, &read(path)?)
+}
+
+// content of a file, '-' reads stdin
+pub fn read(path: &Path) -> Result<String, Error> {
+    let mut source = String::new();
+
+    if path == Path::new("-") {
+        io::stdin().read_to_string(&mut source).context(Kind::Config, "unable to read stdin")?;
+    } else {
+        source = fs::read_to_string(path).context(Kind::Config, &format!("unable to read {}", path.display()))?;
+    }
+
+    Ok(source)
+}
@@ -0,0 +1,109 @@
+use std::io::prelude::*;
+use std::fs::{self, OpenOptions};
+use std::path::PathBuf;
+use std::env;
+use std::sync::Mutex;
+
+use ssh2::{CheckResult, HashType, KnownHostFileKind, KnownHostKeyFormat, Session};
+
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use log::{info, warn};
+
+use crate::error::{Context, Error, Kind};
+
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema, PartialEq)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    // unknown and changed host keys are refused
+    Strict,
+    // unknown host keys are added to known_hosts, changed keys are refused (default)
+    AcceptNew,
+    // host keys are not verified at all
+    Off,
+}
+
+pub struct HostKeys {
+    checking: HostKeyChecking,
+    path: PathBuf,
+    // known_hosts is read and written by all threads
+    lock: Mutex<()>,
+}
+
+impl HostKeys {
+    pub fn new(checking: Option<HostKeyChecking>, path: Option<PathBuf>) -> Self {
+        let path = match path {
+            Some(p) => p,
+            None => PathBuf::from(env::var("HOME").unwrap_or_default()).join(".ssh").join("known_hosts"),
+        };
+
+        HostKeys {
+            checking: checking.unwrap_or(HostKeyChecking::AcceptNew),
+            path,
+            lock: Mutex::new(()),
+        }
+    }
+
+    // check the key of a session after the handshake
+    pub fn verify(&self, session: &Session, host: &str, port: u16) -> Result<(), Error> {
+        if self.checking == HostKeyChecking::Off {
+            return Ok(());
+        }
+
+        let (key, key_type) = session.host_key().ok_or_else(|| Error::new(Kind::Handshake, "server sent no host key"))?;
+        let fingerprint = format!("SHA256:{}", base64::encode_config(session.host_key_hash(HashType::Sha256).unwrap_or_default(), base64::STANDARD_NO_PAD));
+
+        let _guard = self.lock.lock().map_err(|_| Error::new(Kind::HostKey, "known_hosts lock poisoned"))?;
+
+        let mut known_hosts = session.known_hosts().context(Kind::HostKey, "unable to check known_hosts")?;
+        if let Ok(content) = fs::read_to_string(&self.path) {
+            // skip lines libssh2 does not understand (comments, markers, unsupported key types)
+            for line in content.lines() {
+                let _ = known_hosts.read_str(line, KnownHostFileKind::OpenSSH);
+            }
+        }
+
+        match known_hosts.check_port(host, port, key) {
+            CheckResult::Match => Ok(()),
+            CheckResult::Mismatch => Err(Error::new(Kind::HostKeyMismatch, format!("host key {} does not match the one in {}", fingerprint, self.path.display()))),
+            CheckResult::NotFound if self.checking == HostKeyChecking::AcceptNew => {
+                warn!("adding host key {} for {} to {}", fingerprint, host, self.path.display());
+                self.append(session, &entry(host, port), key, key_type.into())
+            },
+            CheckResult::NotFound => Err(Error::new(Kind::HostKey, format!("host key {} not found in {}", fingerprint, self.path.display()))),
+            CheckResult::Failure => Err(Error::new(Kind::HostKey, "unable to check host key")),
+        }
+    }
+
+    // append a single entry, the rest of the file is left untouched
+    fn append(&self, session: &Session, host: &str, key: &[u8], format: KnownHostKeyFormat) -> Result<(), Error> {
+        let message = format!("unable to add host key to {}", self.path.display());
+
+        let mut new_hosts = session.known_hosts().context(Kind::HostKey, &message)?;
+        new_hosts.add(host, key, "", format).context(Kind::HostKey, &message)?;
+
+        let entries = new_hosts.hosts().context(Kind::HostKey, &message)?;
+        let new_host = entries.first().ok_or_else(|| Error::new(Kind::HostKey, message.as_str()))?;
+        let line = new_hosts.write_string(new_host, KnownHostFileKind::OpenSSH).context(Kind::HostKey, &message)?;
+
+        if let Some(directory) = self.path.parent() {
+            fs::create_dir_all(directory).context(Kind::HostKey, &message)?;
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path).context(Kind::HostKey, &message)?;
+        file.write_all(format!("{}\n", line.trim_end()).as_bytes()).context(Kind::HostKey, &message)?;
+
+        info!("host key for {} added to {}", host, self.path.display());
+
+        Ok(())
+    }
+}
+
+// known_hosts entry name, non default ports are written as [host]:port
+fn entry(host: &str, port: u16) -> String {
+    match port {
+        22 => host.to_string(),
+        _ => format!("[{}]:{}", host, port),
+    }
+}
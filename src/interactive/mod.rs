@@ -0,0 +1,116 @@
+use std::io;
+use std::error::Error;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ssh2::{KeyboardInteractivePrompt, Prompt};
+
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+use log::info;
+
+// only one thread may ask on the terminal at the same time
+static TTY: Mutex<()> = Mutex::new(());
+
+// answers keyboard-interactive prompts of the server
+pub struct Prompter<'a> {
+    pub host: &'a str,
+    pub password: Option<String>,
+    pub totp_secret: Option<String>,
+}
+
+impl KeyboardInteractivePrompt for Prompter<'_> {
+    fn prompt<'b>(&mut self, _username: &str, instructions: &str, prompts: &[Prompt<'b>]) -> Vec<String> {
+        if !instructions.is_empty() {
+            info!("{}", instructions);
+        }
+
+        prompts.iter().map(|prompt| self.answer(prompt)).collect()
+    }
+}
+
+impl Prompter<'_> {
+    fn answer(&self, prompt: &Prompt) -> String {
+        let text = prompt.text.to_lowercase();
+        let otp = ["one-time", "otp", "token", "verification", "code", "2fa"].iter().any(|s| text.contains(s));
+
+        let answer = match (otp, &self.password, &self.totp_secret) {
+            (true, _, Some(secret)) => totp(secret).ok(),
+            (false, Some(password), _) if text.contains("password") => Some(password.clone()),
+            _ => None,
+        };
+
+        // everything else is asked on the terminal
+        match answer {
+            Some(answer) => answer,
+            None => ask(&format!("[{}] {}", self.host, prompt.text.trim()), prompt.echo).unwrap_or_default(),
+        }
+    }
+}
+
+fn ask(prompt: &str, echo: bool) -> Result<String, Box<dyn Error>> {
+    let _guard = TTY.lock().map_err(|_| "terminal lock poisoned")?;
+
+    match echo {
+        true => {
+            eprint!("{} ", prompt);
+            let mut answer = String::new();
+            io::stdin().read_line(&mut answer)?;
+            Ok(answer.trim_end_matches(&['\r', '\n'][..]).to_string())
+        },
+        false => Ok(rpassword::read_password_from_tty(Some(&format!("{} ", prompt)))?),
+    }
+}
+
+// time based one-time password (RFC 6238) with 30 seconds and 6 digits
+pub fn totp(secret: &str) -> Result<String, Box<dyn Error>> {
+    totp_at(secret, SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs())
+}
+
+// the password for the seconds since the unix epoch
+fn totp_at(secret: &str, time: u64) -> Result<String, Box<dyn Error>> {
+    let secret = secret.replace(' ', "").to_uppercase();
+    let key = base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret.trim_end_matches('='))
+        .ok_or("totp secret is not valid base32")?;
+
+    let counter = time / 30;
+
+    let mut mac = Hmac::<Sha1>::new_from_slice(&key)?;
+    mac.update(&counter.to_be_bytes());
+    let hash = mac.finalize().into_bytes();
+
+    // dynamic truncation
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let code = u32::from_be_bytes([hash[offset] & 0x7f, hash[offset + 1], hash[offset + 2], hash[offset + 3]]);
+
+    Ok(format!("{:06}", code % 1_000_000))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "12345678901234567890", the sha1 key of the RFC 6238 test vectors
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn rfc6238_test_vectors() {
+        // last 6 of the 8 digits in the RFC
+        let vectors = [(59, "287082"), (1111111109, "081804"), (1111111111, "050471"), (1234567890, "005924"), (2000000000, "279037"), (20000000000, "353130")];
+        for (time, code) in vectors.iter() {
+            assert_eq!(totp_at(SECRET, *time).unwrap(), *code, "time {}", time);
+        }
+    }
+
+    #[test]
+    fn secret_with_spaces_lowercase_and_padding() {
+        assert_eq!(totp_at("gezd gnbv gy3t qojq gezd gnbv gy3t qojq", 59).unwrap(), "287082");
+        assert_eq!(totp_at("GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ====", 59).unwrap(), "287082");
+    }
+
+    #[test]
+    fn invalid_secret() {
+        assert!(totp_at("not base32!", 59).is_err());
+    }
+}
@@ -0,0 +1,255 @@
+use std::env;
+use std::fs;
+
+use serde_json::Value;
+
+use crate::logger;
+
+// fields whose expanded values are secrets
+const SECRETS: &[&str] = &["password", "identity_passphrase", "totp_secret"];
+
+// expand ${NAME}, ${NAME:-default} and ${file:/path} in every string of a
+// parsed config or task file except task commands, file contents and values
+// of secret fields are hidden in the log
+pub fn expand(value: &mut Value) -> Result<(), String> {
+    match value {
+        // a file of included tasks
+        Value::Array(_) => task_list(value),
+        Value::Object(fields) => {
+            for (name, item) in fields.iter_mut() {
+                match name.as_str() {
+                    "targets" | "plays" => {
+                        if let Value::Array(entries) = item {
+                            for entry in entries {
+                                with_tasks(entry)?;
+                            }
+                        }
+                    },
+                    "defaults" => with_tasks(item)?,
+                    // other arrays of objects are task lists, fields like jump
+                    // hosts have no command field
+                    _ => task_list(item)?,
+                }
+            }
+            Ok(())
+        },
+        _ => walk(value, false),
+    }
+}
+
+// a target, play or the defaults with a task list in tasks
+fn with_tasks(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::Object(fields) => {
+            for (name, item) in fields.iter_mut() {
+                match name.as_str() {
+                    "tasks" => task_list(item)?,
+                    name => walk(item, SECRETS.contains(&name))?,
+                }
+            }
+            Ok(())
+        },
+        _ => walk(value, false),
+    }
+}
+
+fn task_list(value: &mut Value) -> Result<(), String> {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                match item {
+                    Value::Object(fields) => {
+                        for (name, field) in fields.iter_mut() {
+                            match name.as_str() {
+                                // commands run on the remote host, ${...} belongs to its shell
+                                "command" => {},
+                                _ => walk(field, false)?,
+                            }
+                        }
+                    },
+                    item => walk(item, false)?,
+                }
+            }
+            Ok(())
+        },
+        _ => walk(value, false),
+    }
+}
+
+fn walk(value: &mut Value, secret: bool) -> Result<(), String> {
+    match value {
+        Value::String(text) => *text = expand_str(text, secret)?,
+        Value::Array(items) => {
+            for item in items {
+                walk(item, false)?;
+            }
+        },
+        Value::Object(fields) => {
+            for (name, item) in fields.iter_mut() {
+                walk(item, SECRETS.contains(&name.as_str()))?;
+            }
+        },
+        _ => {},
+    }
+    Ok(())
+}
+
+// $${ is kept as a literal ${ (e.g. for variables of the remote shell)
+fn expand_str(text: &str, secret: bool) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue
+        }
+        expanded.push_str(&rest[..start]);
+
+        let end = rest[start..].find('}').map(|end| start + end)
+            .ok_or_else(|| format!("unterminated reference in '{}'", text))?;
+        let reference = &rest[start + 2..end];
+
+        let value = match reference.strip_prefix("file:") {
+            Some(path) => {
+                let value = read(path)?;
+                logger::redact(&value);
+                value
+            },
+            None => {
+                let value = variable(reference)?;
+                if secret {
+                    logger::redact(&value);
+                }
+                value
+            },
+        };
+        expanded.push_str(&value);
+        rest = &rest[end + 1..];
+    }
+    expanded.push_str(rest);
+
+    Ok(expanded)
+}
+
+fn variable(reference: &str) -> Result<String, String> {
+    let (name, default) = match reference.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (reference, None),
+    };
+
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("invalid reference ${{{}}}", reference))
+    }
+
+    match (env::var(name), default) {
+        (Ok(value), _) => Ok(value),
+        (Err(_), Some(default)) => Ok(default.to_string()),
+        (Err(_), None) => Err(format!("environment variable {} is not set and ${{{}}} has no default", name, name)),
+    }
+}
+
+// a trailing line break of the file is not part of the value
+fn read(path: &str) -> Result<String, String> {
+    let path = match (path.strip_prefix("~/"), env::var("HOME")) {
+        (Some(rest), Ok(home)) => format!("{}/{}", home, rest),
+        _ => path.to_string(),
+    };
+    let content = fs::read_to_string(&path).map_err(|e| format!("unable to read {}: {}", path, e))?;
+    Ok(content.trim_end_matches(['\n', '\r']).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+
+    #[test]
+    fn command_is_not_expanded() {
+        let mut value = json!({"tasks": [{"command": "cd ${HOME} && echo ${TUI_PATCH_UNSET}", "expected_result": 0}]});
+        expand(&mut value).unwrap();
+        assert_eq!(value["tasks"][0]["command"], "cd ${HOME} && echo ${TUI_PATCH_UNSET}");
+    }
+
+    #[test]
+    fn commands_of_all_task_lists_are_not_expanded() {
+        let task = json!([{"command": "echo ${TUI_PATCH_UNSET}"}]);
+        let mut value = json!({
+            "targets": [{"host": "h1", "tasks": task}],
+            "plays": [{"tasks": task}],
+            "defaults": {"tasks": task},
+            "upgrade": task,
+        });
+        expand(&mut value).unwrap();
+        assert_eq!(value["targets"][0]["tasks"], task);
+        assert_eq!(value["plays"][0]["tasks"], task);
+        assert_eq!(value["defaults"]["tasks"], task);
+        assert_eq!(value["upgrade"], task);
+
+        let mut included = task.clone();
+        expand(&mut included).unwrap();
+        assert_eq!(included, task);
+    }
+
+    #[test]
+    fn other_command_fields_are_expanded() {
+        env::set_var("TUI_PATCH_TEST_COMMAND", "uptime");
+        let mut value = json!({
+            "vars": {"command": "${TUI_PATCH_TEST_COMMAND}"},
+            "targets": [{"host": "h1", "vars": {"command": "${TUI_PATCH_TEST_COMMAND}"}}],
+            "groups": {"web": {"vars": {"command": "${TUI_PATCH_TEST_COMMAND}"}}},
+        });
+        expand(&mut value).unwrap();
+        assert_eq!(value["vars"]["command"], "uptime");
+        assert_eq!(value["targets"][0]["vars"]["command"], "uptime");
+        assert_eq!(value["groups"]["web"]["vars"]["command"], "uptime");
+    }
+
+    #[test]
+    fn escaped_reference_is_literal() {
+        assert_eq!(expand_str("a $${HOME} b", false).unwrap(), "a ${HOME} b");
+        assert_eq!(expand_str("$${X}$${Y}", false).unwrap(), "${X}${Y}");
+        assert_eq!(expand_str("$ and $$ alone", false).unwrap(), "$ and $$ alone");
+    }
+
+    #[test]
+    fn variables_and_defaults() {
+        env::set_var("TUI_PATCH_TEST_USER", "deploy");
+        assert_eq!(expand_str("${TUI_PATCH_TEST_USER}@host", false).unwrap(), "deploy@host");
+        assert_eq!(expand_str("${TUI_PATCH_TEST_USER:-other}", false).unwrap(), "deploy");
+        assert_eq!(expand_str("${TUI_PATCH_TEST_UNSET:-web:8080}", false).unwrap(), "web:8080");
+        assert_eq!(expand_str("${TUI_PATCH_TEST_UNSET:-}", false).unwrap(), "");
+        assert!(expand_str("${TUI_PATCH_TEST_UNSET}", false).is_err());
+    }
+
+    #[test]
+    fn invalid_references() {
+        assert!(expand_str("${1ABC}", false).is_err());
+        assert!(expand_str("${A-B}", false).is_err());
+        assert!(expand_str("${}", false).is_err());
+        assert!(expand_str("${OPEN", false).is_err());
+    }
+
+    #[test]
+    fn file_content_without_line_break() {
+        let path = env::temp_dir().join(format!("tui-patch-test-{}-secret", std::process::id()));
+        fs::write(&path, "s3cret\n").unwrap();
+        let expanded = expand_str(&format!("pw=${{file:{}}}", path.display()), false);
+        let _ = fs::remove_file(&path);
+        assert_eq!(expanded.unwrap(), "pw=s3cret");
+        assert!(expand_str("${file:/nonexistent/tui-patch}", false).is_err());
+    }
+
+    #[test]
+    fn nested_values() {
+        env::set_var("TUI_PATCH_TEST_HOST", "web1");
+        let mut value = json!({"targets": [{"host": "${TUI_PATCH_TEST_HOST}", "port": 22, "vars": {"dir": "$${HOME}"}}]});
+        expand(&mut value).unwrap();
+        assert_eq!(value, json!({"targets": [{"host": "web1", "port": 22, "vars": {"dir": "${HOME}"}}]}));
+    }
+}
@@ -0,0 +1,347 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::fs;
+
+use regex::Regex;
+
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use crate::error::{Context, Error, Kind};
+
+use crate::sshconfig::wildcard;
+
+// hosts of a group, hosts of the child groups are members as well
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct Members {
+    #[serde(default)]
+    hosts: Vec<String>,
+    #[serde(default)]
+    children: Vec<String>,
+}
+
+impl Members {
+    pub fn expand(&mut self) -> Result<(), String> {
+        let mut hosts = Vec::new();
+        for host in &self.hosts {
+            hosts.extend(expand(host)?);
+        }
+        self.hosts = hosts;
+        Ok(())
+    }
+}
+
+// group hierarchy with the depth of every group, top groups have depth 0
+pub struct Groups<'a> {
+    members: BTreeMap<&'a str, &'a Members>,
+    depths: HashMap<&'a str, usize>,
+}
+
+impl<'a> Groups<'a> {
+    // unknown hosts or child groups and groups containing themselves are refused
+    pub fn new(members: BTreeMap<&'a str, &'a Members>, hosts: &[&str]) -> Result<Groups<'a>, Error> {
+        for (name, group) in &members {
+            if *name == "all" {
+                return Err(Error::new(Kind::Config, "group name all is reserved for all hosts"))
+            }
+            for host in &group.hosts {
+                if !hosts.contains(&host.as_str()) {
+                    return Err(Error::new(Kind::Config, format!("group {}: unknown host {}", name, host)))
+                }
+            }
+            for child in &group.children {
+                if !members.contains_key(child.as_str()) {
+                    return Err(Error::new(Kind::Config, format!("group {}: unknown child group {}", name, child)))
+                }
+            }
+        }
+
+        let mut groups = Groups { members, depths: HashMap::new() };
+        let names: Vec<&'a str> = groups.members.keys().copied().collect();
+        for name in names {
+            groups.descend(name, 0, &mut Vec::new())?;
+        }
+
+        Ok(groups)
+    }
+
+    // a child is always deeper than all of its parents
+    fn descend(&mut self, name: &'a str, depth: usize, path: &mut Vec<&'a str>) -> Result<(), Error> {
+        if path.contains(&name) {
+            return Err(Error::new(Kind::Config, format!("group {} contains itself ({} -> {})", name, path.join(" -> "), name)))
+        }
+        if self.depths.get(name).is_some_and(|d| *d >= depth) {
+            return Ok(())
+        }
+        self.depths.insert(name, depth);
+
+        let group = self.members[name];
+        path.push(name);
+        for child in &group.children {
+            self.descend(child.as_str(), depth + 1, path)?;
+        }
+        path.pop();
+
+        Ok(())
+    }
+
+    // groups containing the host directly or through a child group, from
+    // the least to the most specific (by depth, same depth by name)
+    pub fn of(&self, host: &str) -> Vec<&'a str> {
+        let mut found: Vec<&'a str> = self.members.iter()
+            .filter(|(_, group)| group.hosts.iter().any(|h| h == host))
+            .map(|(name, _)| *name)
+            .collect();
+
+        // add the parents until nothing changes
+        let mut i = 0;
+        while i < found.len() {
+            for (name, group) in &self.members {
+                if group.children.iter().any(|c| c == found[i]) && !found.contains(name) {
+                    found.push(name);
+                }
+            }
+            i += 1;
+        }
+
+        found.sort_by_key(|name| (self.depths.get(name).copied().unwrap_or(0), *name));
+        found
+    }
+}
+
+// host pattern like web:db (union), prod:&dmz (intersection) or prod:!dmz
+// (exclusion), terms are separated by ',' or ':' and are host or group names,
+// globs (db*), regular expressions (~web\d+) or files with host names (@failed.txt)
+#[derive(Debug)]
+pub struct Pattern {
+    source: String,
+    terms: Vec<(Operator, Matcher)>,
+}
+
+#[derive(Debug, PartialEq)]
+enum Operator {
+    Union,
+    Intersection,
+    Exclusion,
+}
+
+#[derive(Debug)]
+enum Matcher {
+    Name(String),
+    Glob(String),
+    Regex(Regex),
+    Hosts(Vec<String>),
+}
+
+impl Pattern {
+    pub fn parse(source: &str) -> Result<Pattern, Error> {
+        let mut terms = Vec::new();
+
+        for term in split(source) {
+            let (operator, term) = match (term.strip_prefix('&'), term.strip_prefix('!')) {
+                (Some(term), _) => (Operator::Intersection, term),
+                (_, Some(term)) => (Operator::Exclusion, term),
+                _ => (Operator::Union, term),
+            };
+
+            let matcher = if let Some(expression) = term.strip_prefix('~') {
+                Matcher::Regex(Regex::new(expression).context(Kind::Config, &format!("invalid regular expression in host pattern '{}'", source))?)
+            } else if let Some(path) = term.strip_prefix('@') {
+                let hosts = fs::read_to_string(path).context(Kind::Config, &format!("unable to read host list {}", path))?;
+                Matcher::Hosts(hosts.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')).map(String::from).collect())
+            } else if term.contains(['*', '?']) {
+                Matcher::Glob(term.to_string())
+            } else {
+                Matcher::Name(term.to_string())
+            };
+
+            terms.push((operator, matcher));
+        }
+
+        if terms.is_empty() {
+            return Err(Error::new(Kind::Config, format!("empty host pattern '{}'", source)))
+        }
+
+        Ok(Pattern { source: source.to_string(), terms })
+    }
+
+    // a pattern of only intersections and exclusions starts with all hosts
+    pub fn matches(&self, host: &str, groups: &[String]) -> bool {
+        let names = || std::iter::once(host).chain(groups.iter().map(String::as_str));
+        let hit = |matcher: &Matcher| match matcher {
+            Matcher::Name(name) => name == "all" || names().any(|n| n == name),
+            Matcher::Glob(glob) => names().any(|n| wildcard(glob, n)),
+            Matcher::Regex(regex) => names().any(|n| regex.is_match(n)),
+            Matcher::Hosts(hosts) => hosts.iter().any(|h| h == host),
+        };
+
+        let mut unions = self.terms.iter().filter(|(o, _)| *o == Operator::Union).peekable();
+        let union = unions.peek().is_none() || unions.any(|(_, matcher)| hit(matcher));
+
+        union && self.terms.iter().all(|(operator, matcher)| match operator {
+            Operator::Intersection => hit(matcher),
+            Operator::Exclusion => !hit(matcher),
+            Operator::Union => true,
+        })
+    }
+}
+
+// split at ',' and ':' outside of brackets, e.g. ~db\d{1,2} stays one term
+fn split(source: &str) -> Vec<&str> {
+    let mut terms = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+
+    for (i, c) in source.char_indices() {
+        match c {
+            '(' | '[' | '{' => depth += 1,
+            ')' | ']' | '}' => depth -= 1,
+            ',' | ':' if depth <= 0 => {
+                terms.push(&source[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+    terms.push(&source[start..]);
+
+    terms.into_iter().map(str::trim).filter(|term| !term.is_empty()).collect()
+}
+
+// expand ranges like web[01:40].dc1, db[a:c] or web[01:40:2] (with step),
+// several ranges give every combination, brackets without ':' are kept
+pub fn expand(host: &str) -> Result<Vec<String>, String> {
+    let (open, close) = match host.find('[').and_then(|open| Some((open, open + host[open..].find(']')?))) {
+        Some(brackets) => brackets,
+        None => return Ok(vec![host.to_string()]),
+    };
+    let (prefix, range, rest) = (&host[..open], &host[open + 1..close], &host[close + 1..]);
+
+    let values = match range.split(':').collect::<Vec<&str>>()[..] {
+        [start, end] => range_values(start, end, 1),
+        [start, end, step] => match step.parse::<usize>() {
+            Ok(step) if step > 0 => range_values(start, end, step),
+            _ => None,
+        },
+        _ => Some(vec![format!("[{}]", range)]),
+    }.ok_or_else(|| format!("invalid range [{}] in host {}", range, host))?;
+
+    let suffixes = expand(rest)?;
+    Ok(values.iter().flat_map(|value| suffixes.iter().map(move |suffix| format!("{}{}{}", prefix, value, suffix))).collect())
+}
+
+// numbers keep the width of a start with leading zeros, letters are single characters
+fn range_values(start: &str, end: &str, step: usize) -> Option<Vec<String>> {
+    if let (Ok(first), Ok(last)) = (start.parse::<u64>(), end.parse::<u64>()) {
+        let width = if start.len() > 1 && start.starts_with('0') { start.len() } else { 0 };
+        return match first <= last {
+            true => Some((first..=last).step_by(step).map(|n| format!("{:0width$}", n, width = width)).collect()),
+            false => None,
+        }
+    }
+
+    let mut chars = (start.chars(), end.chars());
+    match (chars.0.next(), chars.0.next(), chars.1.next(), chars.1.next()) {
+        (Some(first), None, Some(last), None) if first.is_ascii_alphabetic() && last.is_ascii_alphabetic() && first <= last => {
+            Some((first..=last).step_by(step).map(String::from).collect())
+        },
+        _ => None,
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn groups(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn expand_keeps_leading_zeros() {
+        assert_eq!(expand("web[08:11].dc1").unwrap(), ["web08.dc1", "web09.dc1", "web10.dc1", "web11.dc1"]);
+        assert_eq!(expand("web[8:10]").unwrap(), ["web8", "web9", "web10"]);
+    }
+
+    #[test]
+    fn expand_with_step() {
+        assert_eq!(expand("web[01:07:3]").unwrap(), ["web01", "web04", "web07"]);
+        assert!(expand("web[1:4:0]").is_err());
+    }
+
+    #[test]
+    fn expand_letters() {
+        assert_eq!(expand("db[a:c]").unwrap(), ["dba", "dbb", "dbc"]);
+        assert_eq!(expand("db[a:e:2]").unwrap(), ["dba", "dbc", "dbe"]);
+        assert!(expand("db[a:1]").is_err());
+        assert!(expand("db[aa:c]").is_err());
+    }
+
+    #[test]
+    fn expand_combines_ranges() {
+        assert_eq!(expand("r[1:2]n[a:b]").unwrap(), ["r1na", "r1nb", "r2na", "r2nb"]);
+    }
+
+    #[test]
+    fn expand_refuses_reversed_ranges() {
+        assert!(expand("web[3:1]").is_err());
+        assert!(expand("db[c:a]").is_err());
+    }
+
+    #[test]
+    fn expand_keeps_brackets_without_range() {
+        assert_eq!(expand("[2001:db8::1]").unwrap(), ["[2001:db8::1]"]);
+        assert_eq!(expand("host[x]").unwrap(), ["host[x]"]);
+        assert_eq!(expand("plain").unwrap(), ["plain"]);
+    }
+
+    #[test]
+    fn split_ignores_separators_in_brackets() {
+        assert_eq!(split("web, db:~db\\d{1,2}:~^app[0-9:]+$"), ["web", "db", "~db\\d{1,2}", "~^app[0-9:]+$"]);
+        assert_eq!(split(" :web,,"), ["web"]);
+    }
+
+    #[test]
+    fn pattern_union() {
+        let pattern = Pattern::parse("web1,db").unwrap();
+        assert!(pattern.matches("web1", &[]));
+        assert!(pattern.matches("db7", &groups(&["db"])));
+        assert!(!pattern.matches("web2", &groups(&["web"])));
+    }
+
+    #[test]
+    fn pattern_intersection_and_exclusion() {
+        let pattern = Pattern::parse("prod:&dmz:!web2").unwrap();
+        assert!(pattern.matches("web1", &groups(&["prod", "dmz"])));
+        assert!(!pattern.matches("web2", &groups(&["prod", "dmz"])));
+        assert!(!pattern.matches("db1", &groups(&["prod"])));
+    }
+
+    #[test]
+    fn pattern_without_union_starts_with_all() {
+        let pattern = Pattern::parse("!db*").unwrap();
+        assert!(pattern.matches("web1", &[]));
+        assert!(!pattern.matches("db1", &[]));
+        assert!(Pattern::parse("all").unwrap().matches("any", &[]));
+    }
+
+    #[test]
+    fn pattern_regex_with_separators() {
+        let pattern = Pattern::parse("~^web\\d{1,2}$:!~^web[0:1]$").unwrap();
+        assert!(pattern.matches("web12", &[]));
+        assert!(!pattern.matches("web1", &[]));
+        assert!(!pattern.matches("web123", &[]));
+    }
+
+    #[test]
+    fn pattern_errors() {
+        assert!(Pattern::parse(" , ").is_err());
+        assert!(Pattern::parse("~web(").is_err());
+    }
+}
@@ -11,11 +11,31 @@ use chrono::{Utc, Datelike, Timelike};
 use log::{Record, Level, LevelFilter, Metadata};
 
 use std::cell::RefCell;
+use std::sync::Mutex;
 
 thread_local! {
     static LOGGER: RefCell<Option<File>> = RefCell::new(None);
 }
 
+// secrets which are replaced by *** in every log line
+static SECRETS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+pub fn redact(secret: &str) {
+    let mut secrets = SECRETS.lock().unwrap();
+    if !secret.is_empty() && !secrets.iter().any(|s| s == secret) {
+        secrets.push(secret.to_string());
+        // longer secrets first, so a secret containing another is hidden completely
+        secrets.sort_by_key(|s| std::cmp::Reverse(s.len()));
+    }
+}
+
+fn mask(mut message: String) -> String {
+    for secret in SECRETS.lock().unwrap().iter() {
+        message = message.replace(secret.as_str(), "***");
+    }
+    message
+}
+
 pub fn init(directory_name: &str, file_prefix: &str) -> Result<(), Box<dyn Error>> {
     let now = Utc::now();
     let file_name = format!("{}/{}_{:04}-{:02}-{:02}_{:02}-{:02}-{:02}.log", directory_name, file_prefix, now.year(), now.month(), now.day(), now.hour(), now.minute(), now.second());
@@ -55,13 +75,14 @@ impl log::Log for FileLogger {
 
     fn log(&self, record: &Record) {
         if self.enabled(record.metadata()) {
+            let message = mask(record.args().to_string());
             LOGGER.with(|rc| {
                 match record.level() {
-                    Level::Error => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 💣 {}: {}", Utc::now(), record.level(), record.args()).as_bytes()).unwrap(),
-                    Level::Warn => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🚧 {}: {}", Utc::now(), record.level(), record.args()).as_bytes()).unwrap(),
-                    Level::Info => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🏁 {}: {}", Utc::now(), record.level(), record.args()).as_bytes()).unwrap(),
-                    Level::Debug => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🐜  {}: {}", Utc::now(), record.level(), record.args()).as_bytes()).unwrap(),
-                    Level::Trace => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🐜  {}: {}", Utc::now(), record.level(), record.args()).as_bytes()).unwrap(),
+                    Level::Error => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 💣 {}: {}", Utc::now(), record.level(), message).as_bytes()).unwrap(),
+                    Level::Warn => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🚧 {}: {}", Utc::now(), record.level(), message).as_bytes()).unwrap(),
+                    Level::Info => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🏁 {}: {}", Utc::now(), record.level(), message).as_bytes()).unwrap(),
+                    Level::Debug => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🐜  {}: {}", Utc::now(), record.level(), message).as_bytes()).unwrap(),
+                    Level::Trace => rc.borrow_mut().as_mut().unwrap().write(format!("\n[{}] 🐜  {}: {}", Utc::now(), record.level(), message).as_bytes()).unwrap(),
                 };  
             });
         }
@@ -1,8 +1,8 @@
-use std::io::prelude::*;
 use std::io;
 
 use structopt::StructOpt;
-use std::path::PathBuf;
+use structopt::clap::AppSettings;
+use std::path::{Path, PathBuf};
 
 use rpassword;
 
@@ -13,38 +13,68 @@ use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
 
 use std::sync::Arc;
 
-use std::fs::File;
+use std::process;
 
 use log::error;
 mod logger;
 
 mod config;
-use config::{Config, State};
+use config::{Config, Play, ShellMode, State, Target};
+
+mod shell;
+use shell::Shell;
+
+mod tunnel;
+use tunnel::Tunnels;
+
+mod interactive;
+
+mod hostkey;
+use hostkey::HostKeys;
+
+mod sshconfig;
+use sshconfig::SshConfig;
+
+mod net;
+
+mod agent;
+
+mod sshoptions;
+
+mod error;
+use error::{Error, Kind};
+
+mod validate;
+
+mod inventory;
+
+mod format;
+use format::Format;
+
+mod interpolate;
 
 mod authenticator;
-use authenticator::{Authenticator, bitwarden::Bitwarden};
+use authenticator::{Stores, bitwarden::Bitwarden, vault::{self, Vault}};
 
 mod resolver;
 use resolver::{Resolver, phpipam::PhpIpam};
 
 // TODO:
 // - update while waiting
-// - add timeout to yaml
-// - check ssh agent running
-// - ssh key added
 // - limit processes
 // - add dependecies of server
 // - alternative file structure with just a list of hosts/tasks
 // - catch all unnesessary unwraps in log
-// - implement own error
 // - change logger to writer
-// - show task number in log
 
 #[derive(Debug, StructOpt)]
-#[structopt(name = "tui-patch", about = "Run SSH commands from a YAML script file in parallel.")]
+#[structopt(name = "tui-patch", about = "Run SSH commands from a YAML script file in parallel.", setting = AppSettings::SubcommandsNegateReqs)]
 struct Opt {
-    #[structopt(parse(from_os_str), help = "YAML script file, for format details see in examples folder.")]
-    config: PathBuf,
+    #[structopt(subcommand)]
+    command: Option<Command>,
+
+    #[structopt(parse(from_os_str), required_unless = "list-keys", help = "YAML, JSON or TOML script file ('-' reads stdin), for format details see in examples folder.")]
+    config: Option<PathBuf>,
 
     #[structopt(default_value = "./log", short, long, help = "Specify the log output directory, the directory will be created if it does not exist. Each logfile will be created with hostname and timestamp.")]
     log: String,
@@ -52,28 +82,203 @@ struct Opt {
     #[structopt(short, long, help = "Pass your Bitwarden master password to unlock the vault. Specify '-' to get prompt to enter hidden password. Setup bitwarden-cli before use (bw login).")]
     bitwarden: Option<String>,
 
+    #[structopt(long, parse(from_os_str), help = "Encrypted vault file with secrets referenced as {from: vault, name: <entry>} in the YAML script file. The passphrase is asked on the terminal unless --vault-key-file is given.")]
+    vault: Option<PathBuf>,
+
+    #[structopt(long, parse(from_os_str), help = "File whose content unlocks the vault instead of a passphrase, also used by the vault subcommands.")]
+    vault_key_file: Option<PathBuf>,
+
     #[structopt(short, long, help = "Provide the URL to your PhpIpam and the PhpIpam App Name and App Code. Make sure you use 'SSL with App code token' in PhpIpam with 'Read' permission.")]
     phpipam: Option<String>,
+
+    #[structopt(long, possible_values = &["yaml", "json", "toml"], help = "Format of the script file (default from the file extension, yaml for stdin and other extensions).")]
+    format: Option<Format>,
+
+    #[structopt(short, long, parse(from_os_str), help = "Inventory file with targets, connection settings and variables shared by several YAML script files, which then only need task lists and an optional 'hosts' pattern.")]
+    inventory: Option<PathBuf>,
+
+    #[structopt(short = "H", long, help = "Host pattern selecting the targets instead of the 'hosts' of the YAML script file, host or group names separated by ':' (union), ':&' (intersection) and ':!' (exclusion), e.g. 'web', 'prod:&dmz' or 'all:!db'.")]
+    hosts: Option<String>,
+
+    #[structopt(long, help = "Run only on the selected targets matching this pattern, e.g. to retry failed hosts. Takes host or group names, globs ('web*'), regular expressions ('~web\\d+'), exclusions ('!db*') and files with one host per line ('@failed.txt'), separated by ',' or ':'.")]
+    limit: Option<String>,
+
+    #[structopt(long, parse(from_os_str), help = "OpenSSH client config used as fallback for host name, user, port, identity file and jump hosts not set in the YAML script file (default ~/.ssh/config).")]
+    ssh_config: Option<PathBuf>,
+
+    #[structopt(long, help = "Print the selected targets with their tasks and exit without connecting.")]
+    dry_run: bool,
+
+    #[structopt(long, help = "List the keys loaded in the SSH agent and exit.")]
+    list_keys: bool,
+}
+
+#[derive(Debug, StructOpt)]
+enum Command {
+    #[structopt(about = "Check a YAML script file and report all problems with line and column, nothing is connected.")]
+    Validate {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    #[structopt(about = "Print the JSON Schema of the YAML script file format, e.g. for editor completion.")]
+    Schema,
+    #[structopt(about = "Print the selected targets one per line with host ranges expanded, nothing is connected.")]
+    ListHosts {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    #[structopt(about = "Create, edit, view or rekey an encrypted vault file with secrets.")]
+    Vault(VaultCommand),
+}
+
+#[derive(Debug, StructOpt)]
+enum VaultCommand {
+    #[structopt(about = "Create a new vault and open it in $VISUAL or $EDITOR.")]
+    Create {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    #[structopt(about = "Open the decrypted vault in $VISUAL or $EDITOR and encrypt it again.")]
+    Edit {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    #[structopt(about = "Print the decrypted vault.")]
+    View {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+    },
+    #[structopt(about = "Encrypt the vault with a new passphrase or key file.")]
+    Rekey {
+        #[structopt(parse(from_os_str))]
+        file: PathBuf,
+
+        #[structopt(long, parse(from_os_str), help = "File whose content is the new key instead of a passphrase.")]
+        new_key_file: Option<PathBuf>,
+    },
 }
 
 fn main() {
     // read parameters
     let args = Opt::from_args();
 
-    // open config file
-    let mut config_file = match File::open(args.config) {
-        Ok(file) => file,
-        Err(error) => panic!("{}", error)
-    };
+    if let Some(Command::Schema) = &args.command {
+        println!("{}", serde_json::to_string_pretty(&Config::schema()).unwrap());
+        return
+    }
 
-    // read config file
-    let mut raw_config = String::new();
-    match config_file.read_to_string(&mut raw_config) {
-        Ok(string) => string,
-        Err(error) => panic!("{}", error)
+    if let Some(Command::Vault(command)) = &args.command {
+        if let Err(error) = run_vault(command, args.vault_key_file.as_deref()) {
+            eprintln!("{}", error);
+            process::exit(error.kind().exit_code())
+        }
+        return
+    }
+
+    if let Some(Command::ListHosts { file }) = &args.command {
+        match format::read(file).and_then(|source| load(file, &source, &args)) {
+            Ok((_, plays)) => {
+                // hosts of several plays are printed once
+                let mut hosts: Vec<&str> = Vec::new();
+                for target in plays.iter().flat_map(|play| &play.targets) {
+                    if !hosts.contains(&target.host.as_str()) {
+                        hosts.push(&target.host);
+                        println!("{}", target.host);
+                    }
+                }
+                return
+            },
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(error.kind().exit_code())
+            }
+        }
+    }
+
+    if let Some(Command::Validate { file }) = &args.command {
+        let mut valid = true;
+        let mut config_source = String::new();
+        for (i, path) in std::iter::once(file).chain(args.inventory.as_ref()).enumerate() {
+            let source = match format::read(path) {
+                Ok(source) => source,
+                Err(error) => {
+                    eprintln!("{}", error);
+                    process::exit(Kind::Config.exit_code())
+                }
+            };
+
+            let format = match (i, args.format) {
+                (0, Some(format)) => format,
+                _ => Format::detect(path),
+            };
+            for problem in validate::check(&source, format, args.inventory.is_none()) {
+                eprintln!("{}:{}", path.display(), problem);
+                valid = false;
+            }
+
+            if i == 0 {
+                config_source = source;
+            }
+        }
+
+        // problems between the files, like unknown task lists or hosts
+        if valid {
+            if let Err(error) = load(file, &config_source, &args) {
+                eprintln!("{}", error);
+                valid = false;
+            }
+        }
+
+        if !valid {
+            process::exit(Kind::Config.exit_code())
+        }
+        println!("{}: ok", file.display());
+        return
+    }
+
+    // show ssh agent keys only
+    if args.list_keys {
+        match agent::identities() {
+            Ok(identities) => {
+                for identity in &identities {
+                    println!("{} {} {}", identity.key_type, identity.fingerprint, identity.comment);
+                }
+                if identities.is_empty() {
+                    println!("ssh agent has no keys loaded.");
+                }
+                return
+            },
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(error.kind().exit_code())
+            }
+        }
+    }
+
+    let path = args.config.clone().unwrap_or_default();
+    let (config, plays) = match format::read(&path).and_then(|source| load(&path, &source, &args)) {
+        Ok(loaded) => loaded,
+        Err(error) => {
+            eprintln!("{}", error);
+            process::exit(error.kind().exit_code())
+        }
     };
-    
-    let config: Config = serde_yaml::from_str(&raw_config).unwrap();
+
+    // show what would run without connecting
+    if args.dry_run {
+        for play in &plays {
+            if play.is_named() {
+                println!("{}:", play);
+            }
+            for target in &play.targets {
+                println!("{}", target);
+                for (i, task) in target.tasks.iter().enumerate() {
+                    println!("  {}. {}", i + 1, task);
+                }
+            }
+        }
+        return
+    }
     
     // setup log
     let log_directory: Arc<String> = Arc::new(args.log);
@@ -118,97 +323,275 @@ fn main() {
     // load bitwarden
     let bitwarden = match &bitwarden_secret {
         // exit if password is wrong
-        Some(path) => Arc::new(
-            match Bitwarden::new(path) {
-                    Ok(bw) => Some(bw),
-                    Err(e) => {
-                        error!("{}", e);
-                        None
-                    },
-                }
-            ),
-        None => Arc::new(None)
+        Some(path) => match Bitwarden::new(path) {
+                Ok(bw) => Some(bw),
+                Err(e) => {
+                    error!("{}", e);
+                    None
+                },
+            },
+        None => None
     };
 
-    // create multithreaded progress bar
-    let multi_progress = MultiProgress::new();
+    // unlock the vault
+    let vault = match &args.vault {
+        Some(path) => match vault::key(args.vault_key_file.as_deref(), "Vault passphrase: ", false).and_then(|key| Vault::new(path, &key)) {
+            Ok(vault) => Some(vault),
+            Err(error) => {
+                eprintln!("{}", error);
+                process::exit(error.kind().exit_code())
+            }
+        },
+        None => None,
+    };
+
+    // references to secret stores are checked before any thread is started
+    let authenticator = Arc::new(Stores::new(bitwarden, vault));
+    if let Err(error) = plays.iter().try_for_each(|play| play.check_secrets(&*authenticator)) {
+        eprintln!("{}", error);
+        process::exit(error.kind().exit_code())
+    }
+
+    // check the ssh agent before any thread is started, only fatal if a host
+    // has no other method to fall back to
+    if plays.iter().any(Play::uses_agent) {
+        if let Err(error) = agent::preflight() {
+            if plays.iter().any(Play::needs_agent) {
+                eprintln!("{}", error);
+                process::exit(error.kind().exit_code())
+            }
+            eprintln!("warning: {}, the next authentication method is used instead", error);
+        }
+    }
+
+    // bastion sessions are shared by all targets behind them and
+    // known_hosts is shared by all threads
+    let shared = Shared {
+        authenticator,
+        resolver,
+        tunnels: Arc::new(Tunnels::new()),
+        host_keys: Arc::new(config.host_keys()),
+        log: log_directory,
+    };
+
+    // plays run one after another, a failed play stops the next ones unless
+    // it is configured to go on; the exit code is the most severe failure
+    let mut code = 0;
+    let count = plays.len();
+    for (i, play) in plays.into_iter().enumerate() {
+        if play.is_named() {
+            println!("{}", play);
+        }
+        if play.targets.is_empty() {
+            println!("no hosts selected, skipped.");
+            continue
+        }
+
+        let stops = play.stops_on_error();
+        let failed = run_play(play, &shared);
+        code = code.max(failed);
+        if failed != 0 && stops && i + 1 < count {
+            eprintln!("play failed, {} following plays skipped.", count - i - 1);
+            break
+        }
+    }
+    process::exit(code)
+}
+
+// read only state shared by the threads of all targets
+#[derive(Clone)]
+struct Shared {
+    authenticator: Arc<Stores>,
+    resolver: Arc<Option<PhpIpam>>,
+    tunnels: Arc<Tunnels>,
+    host_keys: Arc<HostKeys>,
+    log: Arc<String>,
+}
+
+// run the targets of a play in batches of its strategy, a failed host stops
+// the next batches; the exit code of the most severe failure, 1 if a thread
+// panicked
+fn run_play(play: Play, shared: &Shared) -> i32 {
     let style = ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}").progress_chars("##-");
+    let size = play.batch_size();
 
-    for target in config.targets {
-        // add progress bar for thread
-        let count = target.tasks.len();
-        let progress = multi_progress.add(ProgressBar::new(count as u64));
-        progress.set_style(style.clone());
-
-        // create a read only copy for each thread
-        let authenticator = bitwarden.clone();
-        let resolver = resolver.clone();
-        
-        // copy path for logs
-        let log = log_directory.clone();
-        
-        let _ = thread::spawn(move || {
-            progress.set_message(target.host.clone());
-            
-            // create a logfile
-            let _ = logger::init(&*log, &target.host);
-
-            let mut worst_sate = State::Ok;
-
-            match target.connect( &*authenticator, &*resolver) {
-                Ok(c) => {
-                    for task in target.tasks {
-                        match task.run(&c) {
-                            Ok(State::Ok) => {
-                                progress.inc(1);
-                            },
-                            Ok(State::Warning) => {
-                                worst_sate = State::Warning;
-                                progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.magenta/red} {pos:>7}/{len:7} {msg}").progress_chars("##-"));
-                                progress.set_message(format!("{}: warning.", &target.host));
-                                progress.inc(1);
-
-                            },
-                            Ok(State::Failed) => {
-                                progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.magenta/red} {pos:>7}/{len:7} {msg}").progress_chars("##-"));
-                                progress.set_message(format!("{}: command failed.", &target.host));
-                                progress.finish_at_current_pos();
-                                return
-                            },
-                            Err(e) => {
-                                progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.red/red} {pos:>7}/{len:7} {msg}").progress_chars("XX-"));
-                                progress.finish_at_current_pos();
-                                
-                                error!("{}", &e);
-                                return
-                            }
-                        }
+    let mut code = 0;
+    let mut targets = play.targets.into_iter().peekable();
+    while targets.peek().is_some() {
+        if code != 0 {
+            let skipped: Vec<String> = targets.map(|target| target.host).collect();
+            eprintln!("host failed, skipped {}.", skipped.join(", "));
+            break
+        }
+
+        // create multithreaded progress bar
+        let multi_progress = MultiProgress::new();
+        let mut handles = Vec::new();
+
+        for target in targets.by_ref().take(size) {
+            // add progress bar for thread
+            let progress = multi_progress.add(ProgressBar::new(target.tasks.len() as u64));
+            progress.set_style(style.clone());
+
+            // create a read only copy for each thread
+            let shared = shared.clone();
+            handles.push(thread::spawn(move || run_target(target, progress, shared)));
+        }
+
+        // wait for threads to be finished
+        let _ = multi_progress.join();
+
+        code = handles.into_iter()
+            .map(|handle| match handle.join() {
+                Ok(kind) => kind.map_or(0, |k| k.exit_code()),
+                Err(_) => 1,
+            })
+            .max()
+            .unwrap_or(0);
+    }
+    code
+}
+
+fn run_target(target: Target, progress: ProgressBar, shared: Shared) -> Option<Kind> {
+    progress.set_message(target.host.clone());
+    
+    // create a logfile
+    let _ = logger::init(&shared.log, &target.host);
+
+    let mut worst_sate = State::Ok;
+
+    match target.connect(&*shared.authenticator, &*shared.resolver, &shared.tunnels, &shared.host_keys) {
+        Ok(c) => {
+            // open one remote shell for all tasks if requested
+            let mut shell = match target.shell {
+                Some(ShellMode::Persistent) => match Shell::open(&c) {
+                    Ok(s) => Some(s),
+                    Err(e) => {
+                        let e = e.host(&target.host);
+                        progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.red/red} {pos:>7}/{len:7} {msg}").progress_chars("XX-"));
+                        progress.set_message(format!("{}: shell failed.", &target.host));
+                        progress.finish_at_current_pos();
+                        error!("{}", &e);
+                        return Some(e.kind())
                     }
                 },
-                Err(e) => {
-                    progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.red/red} {pos:>7}/{len:7} {msg}").progress_chars("XX-"));
-                    progress.set_message(format!("{}: connection failed.", &target.host));
-                    progress.finish_at_current_pos();
-                    error!("{}", &e);
-                    return
+                _ => None,
+            };
+
+            for (i, task) in target.tasks.iter().enumerate() {
+                let result = match shell.as_mut() {
+                    Some(s) => task.run_in(s),
+                    None => task.run(&c),
+                };
+
+                match result {
+                    Ok(State::Ok) => {
+                        progress.inc(1);
+                    },
+                    Ok(State::Warning) => {
+                        worst_sate = State::Warning;
+                        progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.magenta/red} {pos:>7}/{len:7} {msg}").progress_chars("##-"));
+                        progress.set_message(format!("{}: warning.", &target.host));
+                        progress.inc(1);
+
+                    },
+                    Err(e) => {
+                        let e = e.task(i + 1).host(&target.host);
+                        match e.kind() {
+                            Kind::Assertion => progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.magenta/red} {pos:>7}/{len:7} {msg}").progress_chars("##-")),
+                            _ => progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.red/red} {pos:>7}/{len:7} {msg}").progress_chars("XX-")),
+                        }
+                        progress.set_message(format!("{}: {}.", &target.host, e.kind().label()));
+                        progress.finish_at_current_pos();
+                        
+                        error!("{}", &e);
+                        return Some(e.kind())
+                    }
                 }
             }
 
-            // execution finished for a target
-            match worst_sate {
-                State::Ok => {
-                    progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.green/green} {pos:>7}/{len:7} {msg}").progress_chars("##-"));
-                    progress.finish_with_message(format!("{}: done.", &target.host));
-                },
-                State::Warning => {
-                    progress.finish_with_message(format!("{}: done with warnings.", &target.host));
-                },
-                // unreachable
-                State::Failed => {}
+            if let Some(s) = shell {
+                let _ = s.close();
+            }
+        },
+        Err(e) => {
+            progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.red/red} {pos:>7}/{len:7} {msg}").progress_chars("XX-"));
+            progress.set_message(format!("{}: {}.", &target.host, e.kind().label()));
+            progress.finish_at_current_pos();
+            error!("{}", &e);
+            return Some(e.kind())
+        }
+    }
+
+    // execution finished for a target
+    match worst_sate {
+        State::Ok => {
+            progress.set_style(ProgressStyle::default_bar().template("[{elapsed_precise}] {bar:40.green/green} {pos:>7}/{len:7} {msg}").progress_chars("##-"));
+            progress.finish_with_message(format!("{}: done.", &target.host));
+        },
+        State::Warning => {
+            progress.finish_with_message(format!("{}: done with warnings.", &target.host));
+        },
+    }
+    None
+}
+
+// read, complete and validate the config file and select the hosts of its plays
+fn load(path: &Path, source: &str, args: &Opt) -> Result<(Config, Vec<Play>), Error> {
+    let mut config = parse(path, source, args.format.unwrap_or_else(|| Format::detect(path)), args.inventory.is_none())?;
+
+    // hosts and connection settings shared by several playbooks
+    if let Some(inventory) = &args.inventory {
+        config.merge(parse(inventory, &format::read(inventory)?, Format::detect(inventory), false)?);
+    }
+    config.expand_ranges()?;
+    config.apply_groups()?;
+    config.apply_defaults();
+
+    // read ssh client config
+    let ssh_config = SshConfig::load(args.ssh_config.as_deref())?;
+    config.apply_ssh_config(&ssh_config)?;
+
+    config.validate()?;
+
+    let plays = config.plays(args.hosts.as_deref(), args.limit.as_deref())?;
+    Ok((config, plays))
+}
+
+fn run_vault(command: &VaultCommand, key_file: Option<&Path>) -> Result<(), Error> {
+    match command {
+        VaultCommand::Create { file } => {
+            if file.exists() {
+                return Err(Error::new(Kind::Config, format!("{} already exists", file.display())))
             }
-        });
+            let key = vault::key(key_file, "New vault passphrase: ", true)?;
+            let text = vault::edit("# secrets as name: secret, referenced as {from: vault, name: <name>}\n")?;
+            vault::encrypt(file, &key, &text)
+        },
+        VaultCommand::Edit { file } => {
+            let key = vault::key(key_file, "Vault passphrase: ", false)?;
+            let text = vault::edit(&vault::decrypt(file, &key)?)?;
+            vault::encrypt(file, &key, &text)
+        },
+        VaultCommand::View { file } => {
+            let key = vault::key(key_file, "Vault passphrase: ", false)?;
+            print!("{}", vault::decrypt(file, &key)?);
+            Ok(())
+        },
+        VaultCommand::Rekey { file, new_key_file } => {
+            let key = vault::key(key_file, "Vault passphrase: ", false)?;
+            let text = vault::decrypt(file, &key)?;
+            let new_key = vault::key(new_key_file.as_deref(), "New vault passphrase: ", true)?;
+            vault::encrypt(file, &new_key, &text)
+        },
     }
+}
 
-    // wait for threads to be finished
-    multi_progress.join().unwrap();
-}
\ No newline at end of file
+// a config with its imports and included task files, task list names are
+// only checked if no other file can define them
+fn parse(path: &Path, source: &str, format: Format, task_lists: bool) -> Result<Config, Error> {
+    validate::config(path, source, format, task_lists)?;
+    let mut config: Config = format.parse_expanded(path, source)?;
+    config.resolve_files(path)?;
+    Ok(config)
+}
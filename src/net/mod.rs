@@ -0,0 +1,96 @@
+use std::io;
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::error::{Context, Error, Kind};
+
+// delay before the next address is tried while an attempt is still running (RFC 8305)
+const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+// host and port as written in urls, ipv6 addresses in brackets
+pub fn endpoint(address: &str, port: u16) -> String {
+    match address.parse::<IpAddr>() {
+        Ok(IpAddr::V6(ip)) => format!("[{}]:{}", ip, port),
+        _ => format!("{}:{}", address, port),
+    }
+}
+
+// resolve all addresses and connect to the first one that answers,
+// attempts are started one after another (happy eyeballs) and each has a timeout
+pub fn connect(address: &str, port: u16, timeout: Duration) -> Result<TcpStream, Error> {
+    let addresses = resolve(address, port)?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut pending = 0;
+    let mut errors: Vec<String> = Vec::new();
+
+    for (i, socket_address) in addresses.iter().enumerate() {
+        // give the previous attempt a head start, continue at once if it failed
+        if i > 0 {
+            if let Ok((tried, result)) = receiver.recv_timeout(ATTEMPT_DELAY) {
+                pending -= 1;
+                match result {
+                    Ok(stream) => return connected(tried, stream),
+                    Err(e) => errors.push(failed(tried, e)),
+                }
+            }
+        }
+
+        let sender = sender.clone();
+        let socket_address = *socket_address;
+        thread::spawn(move || {
+            let _ = sender.send((socket_address, TcpStream::connect_timeout(&socket_address, timeout)));
+        });
+        pending += 1;
+    }
+
+    while pending > 0 {
+        match receiver.recv() {
+            Ok((tried, Ok(stream))) => return connected(tried, stream),
+            Ok((tried, Err(e))) => errors.push(failed(tried, e)),
+            Err(_) => break,
+        }
+        pending -= 1;
+    }
+
+    Err(Error::new(Kind::Connect, format!("unable to connect to {}: {}", endpoint(address, port), errors.join(", "))))
+}
+
+fn connected(address: SocketAddr, stream: TcpStream) -> Result<TcpStream, Error> {
+    info!("connected to {}", address);
+    Ok(stream)
+}
+
+fn failed(address: SocketAddr, error: io::Error) -> String {
+    warn!("unable to connect to {}: {}", address, error);
+    format!("{} {}", address, error)
+}
+
+// all addresses of a host, alternating between address families
+fn resolve(address: &str, port: u16) -> Result<Vec<SocketAddr>, Error> {
+    // bare or bracketed ip addresses are used as they are
+    let resolved: Vec<SocketAddr> = match address.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>() {
+        Ok(ip) => vec![SocketAddr::new(ip, port)],
+        Err(_) => (address, port).to_socket_addrs().context(Kind::Resolve, &format!("unable to resolve {}", address))?.collect(),
+    };
+
+    let (first, second): (Vec<SocketAddr>, Vec<SocketAddr>) = match resolved.first() {
+        Some(preferred) => {
+            let preferred_v6 = preferred.is_ipv6();
+            resolved.iter().partition(|a| a.is_ipv6() == preferred_v6)
+        },
+        None => return Err(Error::new(Kind::Resolve, format!("no address found for {}", address))),
+    };
+
+    let mut addresses = Vec::with_capacity(resolved.len());
+    for i in 0..first.len().max(second.len()) {
+        addresses.extend(first.get(i));
+        addresses.extend(second.get(i));
+    }
+
+    Ok(addresses)
+}
@@ -0,0 +1,90 @@
+use std::io;
+use std::io::prelude::*;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ssh2::{Channel, ExtendedData, Session};
+
+use crate::sshoptions;
+
+use crate::error::{Context, Error, Kind};
+
+// one long-lived remote shell, commands are framed with unique markers to
+// split the output and get the exit status of each command
+pub struct Shell {
+    session: Session,
+    channel: Channel,
+    marker: String,
+    counter: usize,
+}
+
+impl Shell {
+    pub fn open(session: &Session) -> Result<Self, Error> {
+        let mut channel = session.channel_session().context(Kind::Channel, "unable to open channel")?;
+
+        // add stderr stream to normal output
+        channel.handle_extended_data(ExtendedData::Merge).context(Kind::Channel, "unable to merge stderr")?;
+        // sh instead of the login shell, the framing of the commands is posix
+        channel.exec("sh").context(Kind::Channel, "unable to start persistent shell")?;
+
+        // marker must never appear in regular command output
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+
+        Ok(Shell {
+            session: session.clone(),
+            channel,
+            marker: format!("__TUI_PATCH_{:x}", nanos),
+            counter: 0,
+        })
+    }
+
+    // run a command in the shell and return its output and exit status
+    pub fn exec(&mut self, command: &str) -> Result<(String, i32), Error> {
+        self.counter += 1;
+        let marker = format!("{}_{}__", self.marker, self.counter);
+
+        // stdin is closed for the command, otherwise it could consume the marker line
+        let framed = format!("{{ {}\n}} < /dev/null\nprintf '\\n%s %d\\n' '{}' \"$?\"\n", command, marker);
+        self.channel.write_all(framed.as_bytes()).context(Kind::Channel, "unable to send command to persistent shell")?;
+        self.channel.flush().context(Kind::Channel, "unable to send command to persistent shell")?;
+
+        let needle = format!("\n{} ", marker).into_bytes();
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut chunk = [0u8; 4096];
+        let mut start = None;
+
+        loop {
+            let size = match sshoptions::read(&self.session, &mut self.channel, &mut chunk) {
+                // e.g. a task called exit, which ends the shell of all tasks
+                Ok(0) => return match self.channel.wait_close().and_then(|_| self.channel.exit_status()) {
+                    Ok(status) => Err(Error::new(Kind::Channel, format!("persistent shell terminated with exit status {}", status))),
+                    Err(_) => Err(Error::new(Kind::Channel, "persistent shell terminated")),
+                },
+                Ok(size) => size,
+                Err(e) if e.kind() == io::ErrorKind::TimedOut => return Err(Error::new(Kind::Timeout, "no output from persistent shell").source(e)),
+                Err(e) => return Err(Error::new(Kind::Channel, "unable to read from persistent shell").source(e)),
+            };
+            buffer.extend_from_slice(&chunk[..size]);
+
+            // only the new chunk and the part of the marker before it are searched
+            if start.is_none() {
+                let from = (buffer.len() - size).saturating_sub(needle.len());
+                start = buffer[from..].windows(needle.len()).position(|window| window == needle.as_slice()).map(|position| from + position);
+            }
+
+            // wait until the exit status line is complete
+            if let Some(start) = start {
+                let rest = &buffer[start + needle.len()..];
+                if let Some(end) = rest.iter().position(|&byte| byte == b'\n') {
+                    let status = String::from_utf8_lossy(&rest[..end]).trim().parse::<i32>().context(Kind::Channel, "invalid exit status from persistent shell")?;
+                    return Ok((String::from_utf8_lossy(&buffer[..start]).to_string(), status));
+                }
+            }
+        }
+    }
+
+    pub fn close(mut self) -> Result<(), Error> {
+        self.channel.write_all(b"exit\n").context(Kind::Channel, "unable to exit persistent shell")?;
+        self.channel.send_eof().context(Kind::Channel, "unable to exit persistent shell")?;
+        self.channel.wait_close().context(Kind::Channel, "unable to exit persistent shell")
+    }
+}
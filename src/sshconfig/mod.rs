@@ -0,0 +1,282 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::env;
+
+use crate::error::{Context, Error, Kind};
+
+// nested includes deeper than this are treated as a loop
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+// parsed OpenSSH client config, only the options used by targets are kept
+#[derive(Debug, Default)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug)]
+struct Block {
+    patterns: Vec<String>,
+    options: Vec<(String, String)>,
+}
+
+// settings for one host, the first obtained value of each option wins
+#[derive(Debug, Default)]
+pub struct HostConfig {
+    pub hostname: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+    pub identity_file: Option<PathBuf>,
+    pub proxy_jump: Option<String>,
+}
+
+impl SshConfig {
+    // read ~/.ssh/config or the given file, the default file may be missing
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let mut config = SshConfig {
+            // options before the first Host line apply to all hosts
+            blocks: vec![Block { patterns: vec!["*".to_string()], options: Vec::new() }],
+        };
+
+        match path {
+            Some(path) => config.read(path, 0)?,
+            None => {
+                let path = ssh_directory().join("config");
+                if path.exists() {
+                    config.read(&path, 0)?;
+                }
+            },
+        }
+
+        Ok(config)
+    }
+
+    fn read(&mut self, path: &Path, depth: usize) -> Result<(), Error> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(Error::new(Kind::Config, format!("too many nested includes in {}", path.display())));
+        }
+
+        let content = fs::read_to_string(path).context(Kind::Config, &format!("unable to read ssh config {}", path.display()))?;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            // keyword and arguments are separated by whitespace or =
+            let (keyword, value) = match line.find(|c: char| c.is_whitespace() || c == '=') {
+                Some(i) => (&line[..i], line[i..].trim_start_matches(|c: char| c.is_whitespace() || c == '=').trim()),
+                None => (line, ""),
+            };
+
+            match keyword.to_lowercase().as_str() {
+                "host" => self.blocks.push(Block {
+                    patterns: split_arguments(value),
+                    options: Vec::new(),
+                }),
+                // match blocks are not supported, their options never apply
+                "match" => self.blocks.push(Block {
+                    patterns: Vec::new(),
+                    options: Vec::new(),
+                }),
+                // included lines belong to the current block until their first Host line
+                "include" => {
+                    for pattern in split_arguments(value) {
+                        let pattern = match Path::new(&pattern).strip_prefix("~") {
+                            Ok(rest) => home_directory().join(rest),
+                            Err(_) if Path::new(&pattern).is_relative() => ssh_directory().join(&pattern),
+                            Err(_) => PathBuf::from(&pattern),
+                        };
+
+                        let mut paths = glob::glob(&pattern.to_string_lossy()).context(Kind::Config, &format!("invalid include in {}", path.display()))?.filter_map(Result::ok).collect::<Vec<PathBuf>>();
+                        paths.sort();
+                        for included in paths {
+                            self.read(&included, depth + 1)?;
+                        }
+                    }
+                },
+                keyword => {
+                    if let Some(block) = self.blocks.last_mut() {
+                        block.options.push((keyword.to_string(), unquote(value)));
+                    }
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn lookup(&self, host: &str) -> HostConfig {
+        let mut config = HostConfig::default();
+
+        for block in self.blocks.iter().filter(|b| matches_host(&b.patterns, host)) {
+            for (keyword, value) in &block.options {
+                match keyword.as_str() {
+                    "hostname" if config.hostname.is_none() => config.hostname = Some(value.replace("%h", host).replace("%%", "%")),
+                    "user" if config.user.is_none() => config.user = Some(value.to_string()),
+                    "port" if config.port.is_none() => config.port = value.parse().ok(),
+                    "identityfile" if config.identity_file.is_none() => config.identity_file = Some(PathBuf::from(value.replace("%h", host).replace("%%", "%"))),
+                    "proxyjump" if config.proxy_jump.is_none() => config.proxy_jump = Some(value.to_string()),
+                    _ => (),
+                }
+            }
+        }
+
+        config
+    }
+}
+
+// a host matches if any pattern matches and no negated pattern does
+fn matches_host(patterns: &[String], host: &str) -> bool {
+    let mut matched = false;
+
+    for pattern in patterns {
+        match pattern.strip_prefix('!') {
+            Some(negated) => if wildcard(&negated.to_lowercase(), &host.to_lowercase()) {
+                return false;
+            },
+            None => if wildcard(&pattern.to_lowercase(), &host.to_lowercase()) {
+                matched = true;
+            },
+        }
+    }
+
+    matched
+}
+
+// match text against a pattern with * (any characters) and ? (one character)
+pub fn wildcard(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            },
+            Some(&c) if c == '?' || c == text[t] => {
+                p += 1;
+                t += 1;
+            },
+            _ => match backtrack {
+                // let the last * consume one more character
+                Some((star, consumed)) => {
+                    p = star + 1;
+                    t = consumed + 1;
+                    backtrack = Some((star, consumed + 1));
+                },
+                None => return false,
+            },
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+// arguments are separated by whitespace, double quotes group them
+fn split_arguments(value: &str) -> Vec<String> {
+    let mut arguments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                if !current.is_empty() {
+                    arguments.push(std::mem::take(&mut current));
+                }
+            },
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        arguments.push(current);
+    }
+
+    arguments
+}
+
+fn unquote(value: &str) -> String {
+    value.trim_matches('"').to_string()
+}
+
+fn home_directory() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap_or_default())
+}
+
+fn ssh_directory() -> PathBuf {
+    home_directory().join(".ssh")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::process;
+
+    // parse a config written to a temporary file
+    fn parse(name: &str, content: &str) -> SshConfig {
+        let path = env::temp_dir().join(format!("tui-patch-test-{}-{}", process::id(), name));
+        fs::write(&path, content).unwrap();
+        let config = SshConfig::load(Some(&path));
+        let _ = fs::remove_file(&path);
+        config.unwrap()
+    }
+
+    #[test]
+    fn wildcard_patterns() {
+        assert!(wildcard("*", ""));
+        assert!(wildcard("web*", "web01"));
+        assert!(wildcard("w?b*.dc?", "web01.dc1"));
+        assert!(wildcard("*.example.*", "a.b.example.com"));
+        assert!(!wildcard("web?", "web"));
+        assert!(!wildcard("*.com", "example.org"));
+    }
+
+    #[test]
+    fn host_negation() {
+        let patterns = vec!["*.example.com".to_string(), "!bastion.example.com".to_string()];
+        assert!(matches_host(&patterns, "web.example.com"));
+        assert!(matches_host(&patterns, "WEB.Example.com"));
+        assert!(!matches_host(&patterns, "bastion.example.com"));
+        // a negation alone never matches
+        assert!(!matches_host(&["!db".to_string()], "web"));
+    }
+
+    #[test]
+    fn first_value_wins() {
+        let config = parse("first", "Host h1\n  HostName real.example.com\n  User sshuser\n  Port 2222\n\nHost *\n  User default\n  Port 22\n  IdentityFile ~/.ssh/%h\n");
+
+        let host = config.lookup("h1");
+        assert_eq!(host.hostname.as_deref(), Some("real.example.com"));
+        assert_eq!(host.user.as_deref(), Some("sshuser"));
+        assert_eq!(host.port, Some(2222));
+        assert_eq!(host.identity_file, Some(PathBuf::from("~/.ssh/h1")));
+
+        let other = config.lookup("h2");
+        assert_eq!(other.hostname, None);
+        assert_eq!(other.user.as_deref(), Some("default"));
+    }
+
+    #[test]
+    fn negated_and_match_blocks() {
+        let config = parse("negated", "User global\nHost * !db1\n  ProxyJump bastion\nMatch host db1\n  User matched\nHost db1\n  HostName=%h.internal\n");
+
+        let db = config.lookup("db1");
+        assert_eq!(db.proxy_jump, None);
+        assert_eq!(db.user.as_deref(), Some("global"));
+        assert_eq!(db.hostname.as_deref(), Some("db1.internal"));
+        assert_eq!(config.lookup("web1").proxy_jump.as_deref(), Some("bastion"));
+    }
+
+    #[test]
+    fn quoted_arguments() {
+        assert_eq!(split_arguments("a \"b c\"  d"), ["a", "b c", "d"]);
+        assert_eq!(unquote("\"/path with space\""), "/path with space");
+    }
+}
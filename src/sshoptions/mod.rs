@@ -0,0 +1,144 @@
+use std::io;
+use std::io::prelude::*;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use ssh2::{Channel, MethodType, Session};
+
+use serde::Deserialize;
+use schemars::JsonSchema;
+
+use crate::error::{Context, Error, Kind};
+
+// session tuning for a target or jump host, applied before the handshake
+#[derive(Debug, Clone, Default, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct SshOptions {
+    // key exchange algorithms in order of preference
+    kex: Option<Vec<String>>,
+    // host key algorithms in order of preference
+    host_key: Option<Vec<String>>,
+    // encryption algorithms in order of preference
+    ciphers: Option<Vec<String>>,
+    // message authentication algorithms in order of preference
+    macs: Option<Vec<String>>,
+    // enable zlib compression
+    compression: Option<bool>,
+    // seconds without traffic until a keepalive message is sent
+    keepalive: Option<u32>,
+}
+
+impl SshOptions {
+    // values set here win over the ones of the given defaults
+    pub fn or(&self, defaults: &SshOptions) -> SshOptions {
+        SshOptions {
+            kex: self.kex.clone().or_else(|| defaults.kex.clone()),
+            host_key: self.host_key.clone().or_else(|| defaults.host_key.clone()),
+            ciphers: self.ciphers.clone().or_else(|| defaults.ciphers.clone()),
+            macs: self.macs.clone().or_else(|| defaults.macs.clone()),
+            compression: self.compression.or(defaults.compression),
+            keepalive: self.keepalive.or(defaults.keepalive),
+        }
+    }
+
+    fn preferences(&self) -> Vec<(MethodType, &'static str, &Vec<String>)> {
+        let mut preferences = Vec::new();
+
+        if let Some(kex) = &self.kex {
+            preferences.push((MethodType::Kex, "kex", kex));
+        }
+        if let Some(host_key) = &self.host_key {
+            preferences.push((MethodType::HostKey, "host_key", host_key));
+        }
+        if let Some(ciphers) = &self.ciphers {
+            preferences.push((MethodType::CryptCs, "ciphers", ciphers));
+            preferences.push((MethodType::CryptSc, "ciphers", ciphers));
+        }
+        if let Some(macs) = &self.macs {
+            preferences.push((MethodType::MacCs, "macs", macs));
+            preferences.push((MethodType::MacSc, "macs", macs));
+        }
+
+        preferences
+    }
+
+    // reject algorithm names the linked libssh2 does not know
+    pub fn validate(&self) -> Result<(), String> {
+        let session = Session::new().map_err(|e| e.to_string())?;
+
+        for (method_type, name, algorithms) in self.preferences() {
+            let supported = session.supported_algs(method_type).map_err(|e| e.to_string())?;
+
+            if algorithms.is_empty() {
+                return Err(format!("ssh_options {} must not be empty", name));
+            }
+            for algorithm in algorithms {
+                if !supported.contains(&algorithm.as_str()) {
+                    return Err(format!("unknown {} algorithm '{}', supported are: {}", name, algorithm, supported.join(", ")));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // must be called before the handshake
+    pub fn apply(&self, session: &Session) -> Result<(), Error> {
+        for (method_type, name, algorithms) in self.preferences() {
+            session.method_pref(method_type, &algorithms.join(",")).context(Kind::Handshake, &format!("unable to set {} algorithms", name))?;
+        }
+
+        session.set_compress(self.compression.unwrap_or(false));
+
+        // keepalive messages are sent while waiting for command output
+        if let Some(interval) = self.keepalive {
+            session.set_keepalive(true, interval);
+        }
+
+        Ok(())
+    }
+}
+
+// read a channel until end of file, keepalive messages are sent while waiting
+// and the session timeout is applied to the time without any data
+pub fn read_to_end(session: &Session, channel: &mut Channel, buffer: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0u8; 4096];
+    let start = buffer.len();
+
+    loop {
+        match read(session, channel, &mut chunk)? {
+            0 => return Ok(buffer.len() - start),
+            size => buffer.extend_from_slice(&chunk[..size]),
+        }
+    }
+}
+
+// same as Channel::read but without blocking the session while waiting
+pub fn read(session: &Session, channel: &mut Channel, buffer: &mut [u8]) -> io::Result<usize> {
+    let timeout = match session.timeout() {
+        0 => None,
+        ms => Some(Duration::from_millis(ms as u64)),
+    };
+    let waiting = Instant::now();
+
+    session.set_blocking(false);
+    let result = loop {
+        match channel.read(buffer) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                if timeout.is_some_and(|t| waiting.elapsed() > t) {
+                    break Err(io::Error::new(io::ErrorKind::TimedOut, "timeout while waiting for data"));
+                }
+
+                // does nothing if keepalive is disabled or the interval has not passed
+                let _ = session.keepalive_send();
+                thread::sleep(Duration::from_millis(10));
+            },
+            // nothing read but the channel is still open
+            Ok(0) if !channel.eof() => thread::sleep(Duration::from_millis(10)),
+            result => break result,
+        }
+    };
+    session.set_blocking(true);
+
+    result
+}
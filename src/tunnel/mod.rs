@@ -0,0 +1,242 @@
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream};
+use std::collections::HashMap;
+
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+use ssh2::{Channel, Session};
+
+use log::info;
+
+use crate::authenticator::Authenticator;
+use crate::config::JumpHost;
+use crate::resolver::Resolver;
+use crate::hostkey::HostKeys;
+use crate::net;
+use crate::error::{Error, Kind};
+
+// a bastion session of one chain, empty until opened or after a failed attempt
+type Slot = Arc<Mutex<Option<Arc<Tunnel>>>>;
+
+// all open bastion sessions, shared by every target behind the same jump hosts
+pub struct Tunnels {
+    tunnels: Mutex<HashMap<String, Slot>>,
+}
+
+impl Tunnels {
+    pub fn new() -> Self {
+        Tunnels {
+            tunnels: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // open a stream to address:port through a chain of jump hosts
+    #[allow(clippy::too_many_arguments)]
+    pub fn connect(&self, chain: &[JumpHost], address: &str, port: u16, timeout: Duration, authenticator: &impl Authenticator, resolver: &Option<impl Resolver>, host_keys: &HostKeys) -> Result<TcpStream, Error> {
+        let tunnel = self.open(chain, timeout, authenticator, resolver, host_keys)?;
+
+        tunnel.forward(address, port).map_err(|e| Error::new(Kind::Connect, format!("unable to forward to {} through {}", net::endpoint(address, port), chain.last().map(|hop| hop.to_string()).unwrap_or_default())).source(e))
+    }
+
+    fn open(&self, chain: &[JumpHost], timeout: Duration, authenticator: &impl Authenticator, resolver: &Option<impl Resolver>, host_keys: &HostKeys) -> Result<Arc<Tunnel>, Error> {
+        let key = chain.iter().map(|hop| hop.to_string()).collect::<Vec<String>>().join(",");
+        let slot = self.tunnels.lock().map_err(|_| Error::new(Kind::Connect, "tunnel registry poisoned"))?
+            .entry(key)
+            .or_default()
+            .clone();
+
+        // opening is serialized per chain, so a bastion session is only created
+        // once while targets behind other jump hosts are not blocked
+        let mut slot = slot.lock().map_err(|_| Error::new(Kind::Connect, "tunnel registry poisoned"))?;
+        if let Some(tunnel) = slot.as_ref() {
+            return Ok(tunnel.clone());
+        }
+
+        let (hop, previous) = chain.split_last().ok_or_else(|| Error::new(Kind::Config, "empty jump host chain"))?;
+        let address = hop.address(resolver);
+
+        // the first hop is reached directly, every other one through its predecessor
+        let stream = match previous.is_empty() {
+            true => net::connect(&address, hop.port(), timeout).map_err(|e| e.host(&hop.to_string()))?,
+            false => match self.open(previous, timeout, authenticator, resolver, host_keys)?.forward(&address, hop.port()) {
+                Ok(stream) => stream,
+                Err(e) => return Err(Error::new(Kind::Connect, format!("unable to forward to jump host {}", hop)).source(e)),
+            },
+        };
+
+        let session = hop.login(stream, authenticator, host_keys)?;
+        info!("jump host {} connected", hop);
+
+        let tunnel = Arc::new(Tunnel::new(session));
+        *slot = Some(tunnel.clone());
+
+        Ok(tunnel)
+    }
+}
+
+struct Request {
+    address: String,
+    port: u16,
+    reply: Sender<io::Result<TcpStream>>,
+}
+
+// a bastion session, channels are driven by a single pump thread
+// because the session can not be used by multiple threads while blocking
+struct Tunnel {
+    requests: Mutex<Sender<Request>>,
+}
+
+impl Tunnel {
+    fn new(session: Session) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || pump(session, receiver));
+
+        Tunnel {
+            requests: Mutex::new(sender),
+        }
+    }
+
+    fn forward(&self, address: &str, port: u16) -> io::Result<TcpStream> {
+        let (reply, response) = mpsc::channel();
+        let closed = || io::Error::new(io::ErrorKind::BrokenPipe, "jump host session closed");
+
+        self.requests.lock().map_err(|_| closed())?.send(Request {
+            address: address.to_string(),
+            port,
+            reply,
+        }).map_err(|_| closed())?;
+
+        response.recv().map_err(|_| closed())?
+    }
+}
+
+struct Forward {
+    channel: Channel,
+    stream: TcpStream,
+    upstream: Vec<u8>,
+    downstream: Vec<u8>,
+    closed: bool,
+}
+
+fn pump(session: Session, requests: Receiver<Request>) {
+    session.set_blocking(false);
+
+    let mut forwards: Vec<Forward> = Vec::new();
+    let mut buffer = [0u8; 16384];
+    let mut disconnected = false;
+
+    loop {
+        // open new channels
+        while !disconnected {
+            match requests.try_recv() {
+                Ok(request) => {
+                    let _ = request.reply.send(open_forward(&session, &request.address, request.port).map(|(forward, stream)| {
+                        forwards.push(forward);
+                        stream
+                    }));
+                },
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => disconnected = true,
+            }
+        }
+
+        // the tunnel is dropped and its last forward closed, ends the thread
+        // and the bastion session with it
+        if disconnected && forwards.is_empty() {
+            return
+        }
+
+        let mut idle = true;
+
+        for forward in forwards.iter_mut() {
+            // local side to remote
+            if forward.upstream.is_empty() {
+                match forward.stream.read(&mut buffer) {
+                    Ok(0) => {
+                        let _ = retry(|| forward.channel.send_eof().map_err(io::Error::from));
+                        forward.closed = true;
+                    },
+                    Ok(n) => forward.upstream.extend_from_slice(&buffer[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(_) => forward.closed = true,
+                }
+            }
+            if !forward.upstream.is_empty() {
+                match forward.channel.write(&forward.upstream) {
+                    Ok(n) => {
+                        forward.upstream.drain(..n);
+                        idle = false;
+                    },
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(_) => forward.closed = true,
+                }
+            }
+
+            // remote side to local
+            if forward.downstream.is_empty() {
+                match forward.channel.read(&mut buffer) {
+                    Ok(0) => {
+                        if forward.channel.eof() {
+                            forward.closed = true;
+                        }
+                    },
+                    Ok(n) => forward.downstream.extend_from_slice(&buffer[..n]),
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(_) => forward.closed = true,
+                }
+            }
+            if !forward.downstream.is_empty() {
+                match forward.stream.write(&forward.downstream) {
+                    Ok(n) => {
+                        forward.downstream.drain(..n);
+                        idle = false;
+                    },
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => (),
+                    Err(_) => forward.closed = true,
+                }
+            }
+        }
+
+        forwards.retain(|forward| !forward.closed);
+
+        // keeps the bastion connection open if keepalive is configured
+        let _ = session.keepalive_send();
+
+        if idle {
+            thread::sleep(Duration::from_millis(2));
+        }
+    }
+}
+
+fn open_forward(session: &Session, address: &str, port: u16) -> io::Result<(Forward, TcpStream)> {
+    let channel = retry(|| session.channel_direct_tcpip(address, port, None).map_err(io::Error::from))?;
+
+    // local socket pair, the client side is handed to the ssh session of the next hop
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    let client = TcpStream::connect(listener.local_addr()?)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nonblocking(true)?;
+
+    Ok((Forward {
+        channel,
+        stream,
+        upstream: Vec::new(),
+        downstream: Vec::new(),
+        closed: false,
+    }, client))
+}
+
+// repeat a call on a non blocking session until it completes
+fn retry<T>(mut call: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+    loop {
+        match call() {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(2)),
+            result => return result,
+        }
+    }
+}
@@ -0,0 +1,821 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+
+use serde::de::{Deserialize, Deserializer, MapAccess, SeqAccess, Visitor};
+use toml::Spanned;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::{Marker, TScalarStyle};
+
+use crate::config::{Config, JumpHost};
+use crate::inventory;
+use crate::format::Format;
+use crate::error::{Error, Kind};
+
+// a problem in the config file, position as line and column (both start at 1)
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Problem {
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{}:{}: {}", line, column, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+// yaml or toml document with the position of every node (line and column),
+// aliases are resolved
+#[derive(Debug, Clone)]
+struct Node {
+    value: Value,
+    position: Option<(usize, usize)>,
+}
+
+#[derive(Debug, Clone)]
+enum Value {
+    // text and if it was written without quotes
+    Scalar(String, bool),
+    Sequence(Vec<Node>),
+    Mapping(Vec<(Node, Node)>),
+}
+
+impl Node {
+    fn scalar(&self) -> Option<&str> {
+        match &self.value {
+            Value::Scalar(s, _) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn plain(&self) -> Option<&str> {
+        match &self.value {
+            Value::Scalar(s, true) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn is_null(&self) -> bool {
+        matches!(self.plain(), Some("" | "~" | "null" | "Null" | "NULL"))
+    }
+
+    fn integer(&self) -> Option<i64> {
+        self.plain().and_then(|s| s.parse().ok())
+    }
+
+    fn boolean(&self) -> Option<bool> {
+        match self.plain() {
+            Some("true" | "True" | "TRUE") => Some(true),
+            Some("false" | "False" | "FALSE") => Some(false),
+            _ => None,
+        }
+    }
+
+    fn position(&self) -> Option<(usize, usize)> {
+        self.position
+    }
+
+    fn line(&self) -> usize {
+        self.position.map_or(0, |(line, _)| line)
+    }
+}
+
+// collects parser events into nodes
+#[derive(Default)]
+struct Builder {
+    // open sequences and mappings with their anchor and items
+    stack: Vec<(Node, usize, Vec<Node>)>,
+    anchors: HashMap<usize, Node>,
+    root: Option<Node>,
+}
+
+impl Builder {
+    fn insert(&mut self, node: Node, anchor: usize) {
+        if anchor > 0 {
+            self.anchors.insert(anchor, node.clone());
+        }
+        match self.stack.last_mut() {
+            Some((_, _, items)) => items.push(node),
+            None => if self.root.is_none() {
+                self.root = Some(node)
+            },
+        }
+    }
+}
+
+impl MarkedEventReceiver for Builder {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        let position = Some((mark.line(), mark.col() + 1));
+        match event {
+            Event::Scalar(s, style, anchor, _) => self.insert(Node { value: Value::Scalar(s, style == TScalarStyle::Plain), position }, anchor),
+            Event::SequenceStart(anchor) => self.stack.push((Node { value: Value::Sequence(Vec::new()), position }, anchor, Vec::new())),
+            Event::MappingStart(anchor) => self.stack.push((Node { value: Value::Mapping(Vec::new()), position }, anchor, Vec::new())),
+            Event::SequenceEnd | Event::MappingEnd => {
+                if let Some((mut node, anchor, items)) = self.stack.pop() {
+                    node.value = match node.value {
+                        Value::Mapping(_) => {
+                            let mut items = items.into_iter();
+                            let mut pairs = Vec::new();
+                            while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                                pairs.push((key, value));
+                            }
+                            // block mappings start at their first key
+                            if let Some((key, _)) = pairs.first() {
+                                node.position = key.position;
+                            }
+                            Value::Mapping(pairs)
+                        },
+                        _ => Value::Sequence(items),
+                    };
+                    self.insert(node, anchor);
+                }
+            },
+            Event::Alias(anchor) => {
+                if let Some(node) = self.anchors.get(&anchor).cloned() {
+                    self.insert(node, 0);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+// toml value with the byte offsets of its keys and values
+enum Toml {
+    Scalar(String, bool),
+    Array(Vec<Spanned<Toml>>),
+    Table(Vec<(Spanned<String>, Spanned<Toml>)>),
+}
+
+impl<'de> Deserialize<'de> for Toml {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TomlVisitor)
+    }
+}
+
+struct TomlVisitor;
+
+// numbers and booleans are plain like unquoted yaml scalars, strings are quoted
+impl<'de> Visitor<'de> for TomlVisitor {
+    type Value = Toml;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a toml value")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Toml, E> {
+        Ok(Toml::Scalar(value.to_string(), true))
+    }
+
+    fn visit_i64<E>(self, value: i64) -> Result<Toml, E> {
+        Ok(Toml::Scalar(value.to_string(), true))
+    }
+
+    fn visit_u64<E>(self, value: u64) -> Result<Toml, E> {
+        Ok(Toml::Scalar(value.to_string(), true))
+    }
+
+    fn visit_f64<E>(self, value: f64) -> Result<Toml, E> {
+        Ok(Toml::Scalar(value.to_string(), true))
+    }
+
+    fn visit_str<E>(self, value: &str) -> Result<Toml, E> {
+        Ok(Toml::Scalar(value.to_string(), false))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Toml, A::Error> {
+        let mut items = Vec::new();
+        while let Some(item) = seq.next_element()? {
+            items.push(item);
+        }
+        Ok(Toml::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Toml, A::Error> {
+        let mut pairs = Vec::new();
+        while let Some(key) = map.next_key()? {
+            pairs.push((key, map.next_value()?));
+        }
+        Ok(Toml::Table(pairs))
+    }
+}
+
+// line and column of a byte offset, tables of [section] headers have no span
+fn location(source: &str, (start, end): (usize, usize)) -> Option<(usize, usize)> {
+    if start == 0 && end == 0 {
+        return None
+    }
+    let before = source.get(..start)?;
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    Some((before.matches('\n').count() + 1, before[line_start..].chars().count() + 1))
+}
+
+fn toml_node(source: &str, toml: Spanned<Toml>) -> Node {
+    let position = location(source, toml.span());
+    match toml.into_inner() {
+        Toml::Scalar(text, plain) => Node { value: Value::Scalar(text, plain), position },
+        Toml::Array(items) => Node { value: Value::Sequence(items.into_iter().map(|item| toml_node(source, item)).collect()), position },
+        Toml::Table(pairs) => {
+            let pairs: Vec<(Node, Node)> = pairs.into_iter().map(|(key, value)| {
+                let key = Node { position: location(source, key.span()), value: Value::Scalar(key.into_inner(), true) };
+                (key, toml_node(source, value))
+            }).collect();
+
+            // like yaml block mappings, tables start at their first key
+            let position = pairs.first().and_then(|(key, _)| key.position).or(position);
+            Node { value: Value::Mapping(pairs), position }
+        },
+    }
+}
+
+// check a config or inventory file without connecting anywhere, all problems
+// are reported with their position instead of stopping at the first one,
+// task list names are only checked for a file without inventory as they can
+// be defined in the other file
+pub fn check(source: &str, format: Format, task_lists: bool) -> Vec<Problem> {
+    // json is read as yaml for the positions
+    let mut builder = Builder::default();
+    let parsed = match format {
+        Format::Toml => match toml::from_str::<Spanned<Toml>>(source) {
+            Ok(toml) => {
+                builder.root = Some(toml_node(source, toml));
+                Ok(())
+            },
+            Err(e) => return vec![Problem {
+                position: e.line_col().map(|(line, column)| (line + 1, column + 1)),
+                message: e.to_string().split(" at line ").next().unwrap_or_default().to_string(),
+            }],
+        },
+        _ => Parser::new(source.chars()).load(&mut builder, false),
+    };
+    if let (Err(e), Format::Yaml) = (&parsed, format) {
+        let message = e.to_string();
+        let message = message.split(" at line ").next().unwrap_or_default();
+        let message = match message {
+            "while parsing node, found unknown anchor" => "reference to an undefined anchor",
+            m => m,
+        };
+        return vec![Problem { position: Some((e.marker().line(), e.marker().col() + 1)), message: message.to_string() }]
+    }
+
+    let mut checker = Checker { problems: Vec::new(), fields: schema_fields(), task_lists: None, task_fields: vec!["command"] };
+    match (&builder.root, format) {
+        (Some(root), _) => checker.config(root, task_lists),
+        (None, Format::Yaml) => checker.problems.push(Problem { position: None, message: "config file is empty".to_string() }),
+        _ => {},
+    }
+
+    // aliased task lists are checked once for every reference
+    let mut problems = checker.problems;
+    problems.sort();
+    problems.dedup();
+
+    // whatever is left is found by the real deserialization
+    if problems.is_empty() {
+        if let Err(e) = format.parse::<Config>(source) {
+            problems.push(Problem { position: e.position, message: e.message });
+        }
+    }
+
+    problems
+}
+
+// the checks of the validate command for a file which is about to be used,
+// all problems are reported in one error
+pub fn config(path: &Path, source: &str, format: Format, task_lists: bool) -> Result<(), Error> {
+    let problems = check(source, format, task_lists);
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(Error::new(Kind::Config, problems.iter().map(|problem| format!("{}:{}", path.display(), problem)).collect::<Vec<String>>().join("\n"))),
+    }
+}
+
+// field names of every struct by its name in the schema (Config for the top
+// level), so fields without a check of their own are not reported as unknown
+fn schema_fields() -> HashMap<String, Vec<String>> {
+    let schema = serde_json::to_value(Config::schema()).unwrap_or_default();
+    let properties = |schema: &serde_json::Value| schema["properties"].as_object().map(|p| p.keys().cloned().collect()).unwrap_or_default();
+
+    let mut fields = HashMap::new();
+    fields.insert("Config".to_string(), properties(&schema));
+    if let Some(definitions) = schema["definitions"].as_object() {
+        for (name, definition) in definitions {
+            fields.insert(name.clone(), properties(definition));
+        }
+    }
+    fields
+}
+
+struct Checker {
+    problems: Vec<Problem>,
+    // fields of the config structs from the schema
+    fields: HashMap<String, Vec<String>>,
+    // defined task lists, none if names are not checked
+    task_lists: Option<Vec<String>>,
+    // required fields of a task, only command if the defaults are not known
+    task_fields: Vec<&'static str>,
+}
+
+impl Checker {
+    fn problem(&mut self, node: &Node, message: String) {
+        self.problems.push(Problem { position: node.position(), message });
+    }
+
+    // fields of a mapping, required fields must be present and not empty
+    fn mapping<'a>(&mut self, node: &'a Node, what: &str, required: &[&str]) -> Vec<(&'a str, &'a Node, &'a Node)> {
+        let pairs = match &node.value {
+            Value::Mapping(pairs) => pairs,
+            _ => {
+                self.problem(node, format!("{} must be a map of fields", what));
+                return Vec::new()
+            },
+        };
+
+        let mut fields: Vec<(&str, &Node, &Node)> = Vec::new();
+        for (key, value) in pairs {
+            match key.scalar() {
+                Some(name) => match fields.iter().find(|(n, _, _)| *n == name) {
+                    Some((_, first, _)) => self.problem(key, format!("duplicate field {}, first set at line {}", name, first.line())),
+                    None => fields.push((name, key, value)),
+                },
+                None => self.problem(key, format!("{} keys must be plain text", what)),
+            }
+        }
+
+        for field in required {
+            match fields.iter().find(|(n, _, _)| n == field) {
+                Some((_, _, value)) if value.is_null() => self.problem(value, format!("{} must not be empty", field)),
+                Some(_) => {},
+                None => self.problem(node, format!("missing field {} in {}", field, what)),
+            }
+        }
+
+        // empty optional fields are the same as not set
+        fields.into_iter().filter(|(_, _, value)| !value.is_null()).collect()
+    }
+
+    fn unknown(&mut self, key: &Node, name: &str, what: &str) {
+        self.problem(key, format!("unknown field {} in {}", name, what));
+    }
+
+    fn known(&self, schema: &str, name: &str) -> bool {
+        self.fields.get(schema).is_some_and(|fields| fields.iter().any(|field| field == name))
+    }
+
+    // a field without a check of its own, unknown if the schema does not have it
+    fn other(&mut self, key: &Node, name: &str, what: &str, schema: &str) {
+        if !self.known(schema, name) {
+            self.unknown(key, name, what);
+        }
+    }
+
+    fn sequence<'a>(&mut self, node: &'a Node, what: &str) -> &'a [Node] {
+        match &node.value {
+            Value::Sequence(items) => items,
+            _ => {
+                self.problem(node, format!("expected a list of {}", what));
+                &[]
+            },
+        }
+    }
+
+    fn string(&mut self, node: &Node, name: &str) {
+        if node.scalar().is_none() {
+            self.problem(node, format!("{} must be text", name));
+        }
+    }
+
+    fn strings(&mut self, node: &Node, name: &str) {
+        for item in self.sequence(node, name) {
+            self.string(item, name);
+        }
+    }
+
+    fn boolean(&mut self, node: &Node, name: &str) {
+        if node.boolean().is_none() {
+            self.problem(node, format!("{} must be true or false", name));
+        }
+    }
+
+    fn integer(&mut self, node: &Node, name: &str, min: i64, max: i64) {
+        match node.integer() {
+            Some(n) if n >= min && n <= max => {},
+            Some(n) => self.problem(node, format!("invalid {} {}, expected {} to {}", name, n, min, max)),
+            None => self.problem(node, format!("{} must be a number", name)),
+        }
+    }
+
+    fn choice(&mut self, node: &Node, name: &str, choices: &[&str]) {
+        match node.plain() {
+            Some(s) if choices.contains(&s) => {},
+            _ => self.problem(node, format!("invalid {} {}, expected one of {}", name, node.scalar().unwrap_or("value"), choices.join(", "))),
+        }
+    }
+
+    // host name, optionally with ranges like web[01:40]
+    fn range(&mut self, node: &Node) {
+        match node.scalar() {
+            Some(host) => if let Err(e) = inventory::expand(host) {
+                self.problem(node, e);
+            },
+            None => self.problem(node, "host must be text".to_string()),
+        }
+    }
+
+    fn port(&mut self, node: &Node) {
+        self.integer(node, "port", 1, u16::MAX as i64);
+    }
+
+    // plain text or a reference to a secret store like {from: env, name: TOKEN}
+    fn secret(&mut self, node: &Node, name: &str) {
+        if node.scalar().is_some() {
+            return
+        }
+
+        let fields = self.mapping(node, name, &["from"]);
+        let from = fields.iter().find(|(n, _, _)| *n == "from").map(|(_, _, value)| *value);
+        let (allowed, required): (&[&str], &[&str]) = match from.map(|from| (from, from.plain())) {
+            Some((_, Some("bitwarden"))) => (&["item", "field"], &[]),
+            Some((_, Some("env"))) => (&["name"], &["name"]),
+            Some((_, Some("command"))) => (&["cmd"], &["cmd"]),
+            Some((_, Some("vault"))) => (&["name"], &["name"]),
+            Some((from, _)) => return self.choice(from, "secret store", &["bitwarden", "env", "command", "vault"]),
+            None => return,
+        };
+
+        for (field, key, value) in &fields {
+            match *field {
+                "from" => {},
+                _ if allowed.contains(field) => self.string(value, field),
+                _ => self.unknown(key, field, name),
+            }
+        }
+        for field in required {
+            if !fields.iter().any(|(n, _, _)| n == field) {
+                self.problem(node, format!("missing field {} in {}", field, name));
+            }
+        }
+    }
+
+    fn auth(&mut self, node: &Node) {
+        for item in self.sequence(node, "authentication methods") {
+            self.choice(item, "authentication method", &["agent", "key", "password", "keyboard-interactive"]);
+        }
+    }
+
+    fn config(&mut self, node: &Node, task_lists: bool) {
+        let fields = self.mapping(node, "config", &[]);
+
+        // task lists can be used before they are defined, imported ones and
+        // imported defaults are only known after reading the imports
+        if task_lists && !fields.iter().any(|(name, _, _)| *name == "import") {
+            let mut names: Vec<String> = fields.iter().map(|(name, _, _)| name.to_string()).filter(|name| !self.known("Config", name)).collect();
+            names.sort();
+            self.task_lists = Some(names);
+
+            // task fields without a default must be set on every task
+            let defaults = fields.iter().find(|(name, _, _)| *name == "defaults").map(|(_, _, value)| *value);
+            let has_default = |field: &str| match defaults.map(|node| &node.value) {
+                Some(Value::Mapping(pairs)) => pairs.iter().any(|(key, value)| key.scalar() == Some(field) && !value.is_null()),
+                _ => false,
+            };
+            self.task_fields = ["command", "expected_result", "stop_on_error"].iter().copied().filter(|field| !has_default(field)).collect();
+        }
+
+        for (name, key, value) in fields {
+            match name {
+                "targets" => self.targets(value),
+                "hosts" => self.string(value, name),
+                "vars" => self.vars(value),
+                "groups" => self.groups(value),
+                "jump" => self.jump(value),
+                "connect_timeout" => self.integer(value, name, 0, i64::MAX),
+                "auth" => self.auth(value),
+                "ssh_options" => self.ssh_options(value),
+                "host_key_checking" => self.choice(value, name, &["strict", "accept-new", "off"]),
+                "known_hosts" => self.string(value, name),
+                "import" => self.strings(value, name),
+                "defaults" => self.defaults(value),
+                "plays" => self.plays(value),
+                name if self.known("Config", name) => {},
+                // a text is an alias of another task list, or a misspelled setting
+                _ if value.scalar().is_some() && self.task_lists.as_ref().is_some_and(|names| !names.iter().any(|n| Some(n.as_str()) == value.scalar())) => {
+                    self.problem(key, format!("unknown field {} (or task list alias of unknown '{}')", name, value.scalar().unwrap_or_default()));
+                },
+                // everything else is a named task list
+                _ => self.target_tasks(value),
+            }
+        }
+    }
+
+    fn targets(&mut self, node: &Node) {
+        let mut hosts: HashMap<(String, i64), usize> = HashMap::new();
+
+        for target in self.sequence(node, "targets") {
+            let fields = self.mapping(target, "target", &["host"]);
+            let mut host: Option<&Node> = None;
+            let mut port = 22;
+
+            for (name, key, value) in fields {
+                match name {
+                    "host" => {
+                        self.range(value);
+                        host = Some(value);
+                    },
+                    "port" => {
+                        self.port(value);
+                        port = value.integer().unwrap_or(port);
+                    },
+                    "ip" | "user" | "identity_file" => self.string(value, name),
+                    "password" | "identity_passphrase" | "totp_secret" => self.secret(value, name),
+                    "auth" => self.auth(value),
+                    "ssh_options" => self.ssh_options(value),
+                    "jump" => self.jump(value),
+                    "connect_timeout" => self.integer(value, name, 0, i64::MAX),
+                    "shell" => self.choice(value, name, &["session", "persistent"]),
+                    "vars" => self.vars(value),
+                    "tasks" => self.target_tasks(value),
+                    _ => self.other(key, name, "target", "Target"),
+                }
+            }
+
+            if let Some((node, host)) = host.and_then(|n| Some((n, n.scalar()?))) {
+                match hosts.get(&(host.to_string(), port)) {
+                    Some(line) => self.problem(node, format!("duplicate target {}:{}, first defined at line {}", host, port, line)),
+                    None => {
+                        hosts.insert((host.to_string(), port), node.line());
+                    },
+                }
+            }
+        }
+    }
+
+    fn groups(&mut self, node: &Node) {
+        for (_, _, group) in self.mapping(node, "groups", &[]) {
+            for (name, key, value) in self.mapping(group, "group", &[]) {
+                match name {
+                    "hosts" => {
+                        for host in self.sequence(value, name) {
+                            self.range(host);
+                        }
+                    },
+                    "children" => self.strings(value, name),
+                    "port" => self.port(value),
+                    "user" | "identity_file" => self.string(value, name),
+                    "password" | "identity_passphrase" | "totp_secret" => self.secret(value, name),
+                    "auth" => self.auth(value),
+                    "ssh_options" => self.ssh_options(value),
+                    "jump" => self.jump(value),
+                    "connect_timeout" => self.integer(value, name, 0, i64::MAX),
+                    "shell" => self.choice(value, name, &["session", "persistent"]),
+                    "vars" => self.vars(value),
+                    _ => self.other(key, name, "group", "Group"),
+                }
+            }
+        }
+    }
+
+    fn vars(&mut self, node: &Node) {
+        for (name, _, value) in self.mapping(node, "vars", &[]) {
+            self.string(value, name);
+        }
+    }
+
+    // a task list name or a list of tasks and task list names
+    fn target_tasks(&mut self, node: &Node) {
+        if node.scalar().is_some() {
+            return self.task_list(node)
+        }
+        for item in self.sequence(node, "tasks") {
+            match item.scalar() {
+                Some(_) => self.task_list(item),
+                None => self.task(item),
+            }
+        }
+    }
+
+    fn task_list(&mut self, node: &Node) {
+        let name = node.scalar().unwrap_or_default();
+        let unknown = self.task_lists.as_ref().filter(|names| !names.iter().any(|n| n == name)).map(|names| names.join(", "));
+        if let Some(names) = unknown {
+            self.problem(node, format!("unknown task list '{}', defined are: {}", name, names));
+        }
+    }
+
+    fn task(&mut self, node: &Node) {
+        if let Value::Mapping(pairs) = &node.value {
+            if pairs.iter().any(|(key, _)| key.scalar() == Some("include")) {
+                return self.include(node)
+            }
+        }
+
+        let required = self.task_fields.clone();
+        for (name, key, value) in self.mapping(node, "task", &required) {
+            match name {
+                "command" => self.string(value, name),
+                "expected_result" => self.integer(value, name, i32::MIN as i64, i32::MAX as i64),
+                "stop_on_error" | "become" => self.boolean(value, name),
+                _ => self.other(key, name, "task", "Task"),
+            }
+        }
+    }
+
+    fn defaults(&mut self, node: &Node) {
+        for (name, key, value) in self.mapping(node, "defaults", &[]) {
+            match name {
+                "user" => self.string(value, name),
+                "port" => self.port(value),
+                "auth" => self.auth(value),
+                "connect_timeout" => self.integer(value, name, 0, i64::MAX),
+                "shell" => self.choice(value, name, &["session", "persistent"]),
+                "tasks" => self.target_tasks(value),
+                "expected_result" => self.integer(value, name, i32::MIN as i64, i32::MAX as i64),
+                "stop_on_error" | "become" => self.boolean(value, name),
+                _ => self.other(key, name, "defaults", "Defaults"),
+            }
+        }
+    }
+
+    fn plays(&mut self, node: &Node) {
+        for play in self.sequence(node, "plays") {
+            for (name, key, value) in self.mapping(play, "play", &[]) {
+                match name {
+                    "name" | "hosts" => self.string(value, name),
+                    "tasks" => self.target_tasks(value),
+                    "strategy" => self.choice(value, name, &["parallel", "serial"]),
+                    "batch" => self.integer(value, name, 1, i64::MAX),
+                    "stop_on_error" => self.boolean(value, name),
+                    _ => self.other(key, name, "play", "Play"),
+                }
+            }
+        }
+    }
+
+    // a file with tasks, its content is checked when it is read
+    fn include(&mut self, node: &Node) {
+        for (name, key, value) in self.mapping(node, "include", &["include"]) {
+            match name {
+                "include" => self.string(value, name),
+                _ => self.unknown(key, name, "include"),
+            }
+        }
+    }
+
+    fn jump(&mut self, node: &Node) {
+        match &node.value {
+            Value::Sequence(hops) => {
+                for hop in hops {
+                    self.hop(hop);
+                }
+            },
+            _ => self.hop(node),
+        }
+    }
+
+    fn hop(&mut self, node: &Node) {
+        if let Some(s) = node.scalar() {
+            if let Err(e) = JumpHost::parse(s) {
+                self.problem(node, e);
+            }
+            return
+        }
+
+        for (name, key, value) in self.mapping(node, "jump host", &["host"]) {
+            match name {
+                "port" => self.port(value),
+                "host" | "user" | "identity_file" => self.string(value, name),
+                "password" | "identity_passphrase" | "totp_secret" => self.secret(value, name),
+                "auth" => self.auth(value),
+                "ssh_options" => self.ssh_options(value),
+                _ => self.other(key, name, "jump host", "JumpFields"),
+            }
+        }
+    }
+
+    fn ssh_options(&mut self, node: &Node) {
+        for (name, key, value) in self.mapping(node, "ssh_options", &[]) {
+            match name {
+                "kex" | "host_key" | "ciphers" | "macs" => self.strings(value, name),
+                "compression" => self.boolean(value, name),
+                "keepalive" => self.integer(value, name, 0, u32::MAX as i64),
+                _ => self.other(key, name, "ssh_options", "SshOptions"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<String> {
+        check(source, Format::Yaml, true).iter().map(|problem| problem.to_string()).collect()
+    }
+
+    const TASKS: &str = "t:\n  - command: ls\n    expected_result: 0\n    stop_on_error: true\n";
+
+    #[test]
+    fn valid_config() {
+        assert_eq!(messages(&format!("targets:\n  - host: h1\n    user: u\n    tasks: t\n{}", TASKS)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let source = format!("targets:\n  - host: h1\n    pasword: x\n    tasks: t\n    ssh_options:\n      compress: true\n{}", TASKS);
+        assert_eq!(messages(&source), ["3:5: unknown field pasword in target", "6:7: unknown field compress in ssh_options"]);
+    }
+
+    #[test]
+    fn missing_fields() {
+        let source = "targets:\n  - user: u\n    tasks: t\nt:\n  - command: ls\n";
+        assert_eq!(messages(source), ["2:5: missing field host in target", "5:5: missing field expected_result in task", "5:5: missing field stop_on_error in task"]);
+    }
+
+    #[test]
+    fn defaults_make_task_fields_optional() {
+        let source = "defaults:\n  expected_result: 0\n  stop_on_error: true\ntargets:\n  - host: h1\n    tasks: t\nt:\n  - command: ls\n";
+        assert_eq!(messages(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn ports() {
+        let source = format!("targets:\n  - host: h1\n    port: 0\n    tasks: t\n  - host: h2\n    port: 65536\n    tasks: t\n  - host: h3\n    port: ssh\n    tasks: t\n{}", TASKS);
+        assert_eq!(messages(&source), ["3:11: invalid port 0, expected 1 to 65535", "6:11: invalid port 65536, expected 1 to 65535", "9:11: port must be a number"]);
+    }
+
+    #[test]
+    fn duplicate_targets() {
+        let source = format!("targets:\n  - host: h1\n    tasks: t\n  - host: h1\n    port: 22\n    tasks: t\n  - host: h1\n    port: 2222\n    tasks: t\n{}", TASKS);
+        assert_eq!(messages(&source), ["4:11: duplicate target h1:22, first defined at line 2"]);
+    }
+
+    #[test]
+    fn dangling_task_lists() {
+        let source = format!("targets:\n  - host: h1\n    tasks: [t, missing]\nplays:\n  - tasks: other\n{}", TASKS);
+        assert_eq!(messages(&source), ["3:16: unknown task list 'missing', defined are: t", "5:12: unknown task list 'other', defined are: t"]);
+    }
+
+    #[test]
+    fn misspelled_setting() {
+        let source = format!("host_key_cheking: strict\nalias: t\ntargets:\n  - host: h1\n    tasks: alias\n{}", TASKS);
+        assert_eq!(messages(&source), ["1:1: unknown field host_key_cheking (or task list alias of unknown 'strict')"]);
+    }
+
+    #[test]
+    fn task_lists_of_other_files_are_not_checked() {
+        let source = "import: [common.yaml]\ntargets:\n  - host: h1\n    tasks: upgrade\n";
+        assert_eq!(messages(source), Vec::<String>::new());
+    }
+
+    #[test]
+    fn invalid_choices_and_secrets() {
+        let source = format!("host_key_checking: loose\ntargets:\n  - host: h1\n    auth: [agent, otp]\n    password: {{from: keyring}}\n    tasks: t\n{}", TASKS);
+        assert_eq!(messages(&source), [
+            "1:20: invalid host_key_checking loose, expected one of strict, accept-new, off",
+            "4:19: invalid authentication method otp, expected one of agent, key, password, keyboard-interactive",
+            "5:22: invalid secret store keyring, expected one of bitwarden, env, command, vault",
+        ]);
+    }
+
+    #[test]
+    fn yaml_errors() {
+        assert_eq!(messages("targets: *missing\n"), ["1:10: reference to an undefined anchor"]);
+        assert_eq!(messages(""), ["config file is empty"]);
+    }
+
+    #[test]
+    fn toml_positions() {
+        let source = "hosts = \"web\"\n\n[[targets]]\nhost = \"h1\"\npasword = \"x\"\nport = 0\ntasks = [{ command = \"ls\" }]\n\n[groups.web]\nhosts = [\"h1\"]\n";
+        let messages: Vec<String> = check(source, Format::Toml, true).iter().map(|problem| problem.to_string()).collect();
+        assert_eq!(messages, [
+            "5:1: unknown field pasword in target",
+            "6:8: invalid port 0, expected 1 to 65535",
+            "7:12: missing field expected_result in task",
+            "7:12: missing field stop_on_error in task",
+        ]);
+    }
+
+    #[test]
+    fn toml_errors() {
+        let messages: Vec<String> = check("hosts = \"web\n", Format::Toml, true).iter().map(|problem| problem.to_string()).collect();
+        assert_eq!(messages, ["1:13: newline in string found"]);
+    }
+
+    // every field of the schema is known to the checker
+    #[test]
+    fn schema_fields_are_known() {
+        let checker = Checker { problems: Vec::new(), fields: schema_fields(), task_lists: None, task_fields: Vec::new() };
+        for (schema, names) in &checker.fields {
+            for name in names {
+                assert!(checker.known(schema, name), "{}.{}", schema, name);
+            }
+        }
+        for schema in &["Config", "Target", "Group", "Task", "Defaults", "Play", "JumpFields", "SshOptions"] {
+            assert!(!checker.fields[*schema].is_empty(), "{} has no fields", schema);
+        }
+    }
+}